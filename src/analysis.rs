@@ -0,0 +1,233 @@
+use std::fmt::Display;
+
+use crate::{Op, Position};
+
+pub struct Warning {
+    pub pos: Position,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "warning at {}: {}", self.pos, self.message)
+    }
+}
+
+/// Runs of `+` or `-` at least this long are reported as candidates for a
+/// loop.
+const LONG_RUN: usize = 16;
+
+/// Statically scans the program for common beginner mistakes. Nothing is
+/// executed, the warnings are purely advisory.
+pub fn lint(ops: &[Op], positions: &[Position]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    // whether the current cell is known to be zero: true at the start of the
+    // program and right after a loop
+    let mut known_zero = true;
+
+    for (i, &op) in ops.iter().enumerate() {
+        let next = ops.get(i + 1).copied();
+
+        match (op, next) {
+            (Op::Incr, Some(Op::Decr))
+            | (Op::Decr, Some(Op::Incr))
+            | (Op::Left, Some(Op::Right))
+            | (Op::Right, Some(Op::Left)) => warnings.push(Warning {
+                pos: positions[i],
+                message: format!("`{}{}` cancels out (wasted operations)", op, next.unwrap()),
+            }),
+            (Op::Close, Some(Op::Open)) => warnings.push(Warning {
+                pos: positions[i],
+                message: "`][` is likely a typo, a loop right after a loop never runs".to_string(),
+            }),
+            _ => {}
+        }
+
+        let starts_run = i == 0 || ops[i - 1] != op;
+        if starts_run && (op == Op::Incr || op == Op::Decr) {
+            let run = ops[i..].iter().take_while(|&&o| o == op).count();
+            if run >= LONG_RUN {
+                warnings.push(Warning {
+                    pos: positions[i],
+                    message: format!("run of {} `{}` could be written with a loop", run, op),
+                });
+            }
+            if known_zero && op == Op::Incr && run % 256 != 0 && ops.get(i + run) == Some(&Op::Open)
+            {
+                warnings.push(Warning {
+                    pos: positions[i + run],
+                    message:
+                        "`[` on a cell that is guaranteed to be nonzero, the loop is always entered"
+                            .to_string(),
+                });
+            }
+        }
+
+        known_zero = op == Op::Close;
+    }
+
+    warnings.extend(infinite_loops(ops, positions));
+    warnings.sort_by_key(|w| (w.pos.line, w.pos.col));
+
+    warnings
+}
+
+/// Finds the loops that never end once entered, because their body can't
+/// change the cell they test (see `modifies_controlling_cell`).
+pub fn infinite_loops(ops: &[Op], positions: &[Position]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    let mut open_brackets = Vec::new();
+    for (i, &op) in ops.iter().enumerate() {
+        match op {
+            Op::Open => open_brackets.push(i),
+            Op::Close => {
+                if let Some(start) = open_brackets.pop() {
+                    let body = &ops[start + 1..i];
+                    if body.is_empty() {
+                        warnings.push(Warning {
+                            pos: positions[start],
+                            message: "infinite: `[]` never ends on a nonzero cell".to_string(),
+                        });
+                    } else if !modifies_controlling_cell(body) {
+                        warnings.push(Warning {
+                            pos: positions[start],
+                            message: "potentially infinite: controlling cell not modified in body"
+                                .to_string(),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    warnings.sort_by_key(|w| (w.pos.line, w.pos.col));
+
+    warnings
+}
+
+/// Whether a loop body may modify the cell the loop tests. Bodies containing
+/// loops or input, or that don't come back to the controlling cell, are
+/// assumed to modify it.
+fn modifies_controlling_cell(body: &[Op]) -> bool {
+    let mut offset = 0isize;
+    for op in body {
+        match op {
+            Op::Left => offset -= 1,
+            Op::Right => offset += 1,
+            Op::Incr | Op::Decr if offset == 0 => return true,
+            Op::Open | Op::Close | Op::In => return true,
+            _ => {}
+        }
+    }
+    offset != 0
+}
+
+pub struct InputEstimate {
+    /// Number of `,` outside of any loop, each reads exactly once.
+    pub fixed: usize,
+    /// Number of `,` inside loops, each can read any number of times.
+    pub in_loops: usize,
+}
+
+/// Roughly estimates how much input the program reads, without executing it.
+pub fn input_estimate(ops: &[Op]) -> InputEstimate {
+    let mut estimate = InputEstimate {
+        fixed: 0,
+        in_loops: 0,
+    };
+
+    let mut depth = 0usize;
+    for op in ops {
+        match op {
+            Op::Open => depth += 1,
+            Op::Close => depth = depth.saturating_sub(1),
+            Op::In if depth == 0 => estimate.fixed += 1,
+            Op::In => estimate.in_loops += 1,
+            _ => {}
+        }
+    }
+
+    estimate
+}
+
+pub struct Complexity {
+    pub max_depth: usize,
+    pub loops: usize,
+    /// Number of cells between the leftmost and rightmost pointer positions,
+    /// following the ops in order without repeating loops.
+    pub pointer_span: usize,
+    /// Number of different op types used (out of `Op::COUNT`).
+    pub op_variety: usize,
+}
+
+impl Complexity {
+    /// Heuristic combining all the factors into a single number, deeper
+    /// nesting weighs the most.
+    pub fn score(&self) -> usize {
+        self.max_depth * 10 + self.loops * 2 + self.pointer_span + self.op_variety * 5
+    }
+}
+
+/// Computes static features characterizing how gnarly a program is.
+pub fn complexity(ops: &[Op]) -> Complexity {
+    let mut depth = 0;
+    let mut max_depth = 0;
+    let mut loops = 0;
+    let mut offset = 0isize;
+    let (mut min_offset, mut max_offset) = (0, 0);
+    let mut used = [false; Op::COUNT];
+
+    for &op in ops {
+        used[op.index()] = true;
+        match op {
+            Op::Open => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+                loops += 1;
+            }
+            Op::Close => depth -= 1,
+            Op::Left => offset -= 1,
+            Op::Right => offset += 1,
+            _ => {}
+        }
+        min_offset = min_offset.min(offset);
+        max_offset = max_offset.max(offset);
+    }
+
+    Complexity {
+        max_depth,
+        loops,
+        pointer_span: (max_offset - min_offset) as usize + 1,
+        op_variety: used.iter().filter(|&&u| u).count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn infinite_loop_warnings(program: &str) -> Vec<String> {
+        let program = parse::parse(program).unwrap();
+        infinite_loops(&program.ops, &program.positions)
+            .iter()
+            .map(Warning::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn only_loops_that_cant_end_warn() {
+        assert_eq!(
+            infinite_loop_warnings("+[]"),
+            ["warning at 1:2: infinite: `[]` never ends on a nonzero cell"]
+        );
+        assert_eq!(
+            infinite_loop_warnings("+\n[><.]"),
+            ["warning at 2:1: potentially infinite: controlling cell not modified in body"]
+        );
+        assert!(infinite_loop_warnings("++[->+<]>[>]").is_empty());
+        assert!(infinite_loop_warnings("+[,]").is_empty());
+    }
+}
@@ -1,6 +1,61 @@
-use std::{collections::HashMap, env};
+use std::{collections::HashMap, env, path::Path};
 
-pub fn parse() -> (Vec<String>, HashMap<String, Option<String>>) {
+/// Options whose parameter is a program, taken even when it starts with `-`
+/// (e.g. `-e -[>+<-]`).
+const PROGRAM_OPTIONS: [&str; 2] = ["--eval", "-e"];
+
+/// Stands for the `-` program path.
+const STDIN_OPTION: &str = "--stdin";
+
+/// What to do with the program, given before the program paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Executes the program, the default.
+    Run,
+    Check,
+    Fmt,
+    Compile,
+    /// Executes the program in the debugger.
+    Debug,
+}
+
+const SUBCOMMANDS: [(&str, Command); 5] = [
+    ("run", Command::Run),
+    ("check", Command::Check),
+    ("fmt", Command::Fmt),
+    ("compile", Command::Compile),
+    ("debug", Command::Debug),
+];
+
+/// Options that only change how the program is read, the only ones the
+/// commands that don't execute it accept.
+const PARSE_OPTIONS: [&str; 4] = ["eval", "strict", "extended", "no-color"];
+
+impl Command {
+    pub fn name(self) -> &'static str {
+        SUBCOMMANDS
+            .iter()
+            .find(|&&(_, command)| command == self)
+            .map_or("run", |(name, _)| name)
+    }
+
+    /// Whether the option applies to the command, every option applies to
+    /// the ones executing the program.
+    pub fn accepts(self, option: &str) -> bool {
+        let own: &[&str] = match self {
+            Command::Run | Command::Debug => return true,
+            Command::Check => &["check"],
+            Command::Fmt => &["fmt"],
+            Command::Compile => &["output", "optimize", "O0", "O1", "O2", "low-memory"],
+        };
+        PARSE_OPTIONS.contains(&option) || own.contains(&option)
+    }
+}
+
+/// Other names of long options, stored under the name they stand for.
+const ALIASES: [(&str, &str); 1] = [("eof-mode", "eof")];
+
+pub fn parse() -> (Command, Vec<String>, HashMap<String, Option<String>>) {
     let mut args = Vec::new();
     let mut options = HashMap::new();
 
@@ -10,7 +65,15 @@ pub fn parse() -> (Vec<String>, HashMap<String, Option<String>>) {
     let mut i = 0;
     while i < l {
         let arg = raw_args[i].to_string();
-        if let Some(stripped) = arg.strip_prefix("--") {
+        if arg == "-" || arg == STDIN_OPTION {
+            args.push("-".to_string());
+        } else if PROGRAM_OPTIONS.contains(&arg.as_str()) {
+            let param = raw_args.get(i + 1).map(|s| s.to_owned());
+            if param.is_some() {
+                i += 1;
+            }
+            options.insert("eval".to_string(), param);
+        } else if let Some(stripped) = arg.strip_prefix("--") {
             let param = raw_args
                 .get(i + 1)
                 .map(|s| s.to_owned())
@@ -19,7 +82,11 @@ pub fn parse() -> (Vec<String>, HashMap<String, Option<String>>) {
             if param.is_some() {
                 i += 1;
             }
-            options.insert(stripped.to_string(), param);
+            let name = ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == stripped)
+                .map_or(stripped, |(_, name)| name);
+            options.insert(name.to_string(), param);
         } else if let Some(stripped) = arg.strip_prefix("-") {
             options.insert(stripped.to_string(), None);
         } else {
@@ -28,5 +95,18 @@ pub fn parse() -> (Vec<String>, HashMap<String, Option<String>>) {
         i += 1;
     }
 
-    (args, options)
+    // a file named like a command is a program path
+    let command = match args
+        .get(1)
+        .filter(|arg| !Path::new(arg).is_file())
+        .and_then(|arg| SUBCOMMANDS.iter().find(|(name, _)| name == arg))
+    {
+        Some(&(_, command)) => {
+            args.remove(1);
+            command
+        }
+        None => Command::Run,
+    };
+
+    (command, args, options)
 }
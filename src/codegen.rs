@@ -0,0 +1,207 @@
+use std::fmt::Write;
+
+use crate::{optimize, CellSize, Eof, Op, OpList};
+
+/// Deltas up to this size are written as plain `+`/`-` runs, bigger ones
+/// use a multiplication loop on the cell to the right.
+const MAX_PLAIN_DELTA: u8 = 10;
+
+/// Generates a program printing `text` (as utf-8 bytes). Only the first two
+/// cells are used: the first holds the byte to print, the second is a loop
+/// counter which is always cleared after use.
+pub fn print_program(text: &str) -> String {
+    let mut program = String::new();
+
+    let mut current = 0u8;
+    for &b in text.as_bytes() {
+        let delta = b.wrapping_sub(current);
+        let (op, n) = if delta <= 128 {
+            ('+', delta)
+        } else {
+            ('-', delta.wrapping_neg())
+        };
+
+        if n > MAX_PLAIN_DELTA {
+            // n = a * b + r
+            let a = (n as f32).sqrt() as u8;
+            let b = n / a;
+            let r = n - a * b;
+            program.push('>');
+            program.extend((0..a).map(|_| '+'));
+            program.push_str("[<");
+            program.extend((0..b).map(|_| op));
+            program.push_str(">-]<");
+            program.extend((0..r).map(|_| op));
+        } else {
+            program.extend((0..n).map(|_| op));
+        }
+        program.push('.');
+
+        current = b;
+    }
+
+    program
+}
+
+/// Translates the program to a standalone C program with a static tape of
+/// `tape_len` cells. Runs are folded so they become a single statement, the
+/// output matches the interpreter's for programs that stay on the tape.
+pub fn c_program(op_list: OpList, cell_size: CellSize, eof: Eof, tape_len: usize) -> String {
+    let cell_type = match cell_size {
+        CellSize::U8 => "uint8_t",
+        CellSize::U16 => "uint16_t",
+        CellSize::U32 => "uint32_t",
+    };
+
+    let mut c = String::new();
+    c.push_str("#include <stdint.h>\n#include <stdio.h>\n\n");
+    let _ = writeln!(c, "static {} tape[{}];\n", cell_type, tape_len);
+    if cell_size != CellSize::U8 {
+        // same as the interpreter: the UTF-8 encoding of the char, or the
+        // low byte when the value isn't one
+        c.push_str(
+            "static void put(uint32_t c) {
+    if (c < 0x80 || (c >= 0xd800 && c < 0xe000) || c >= 0x110000) {
+        putchar(c & 0xff);
+    } else if (c < 0x800) {
+        putchar(0xc0 | c >> 6);
+        putchar(0x80 | (c & 0x3f));
+    } else if (c < 0x10000) {
+        putchar(0xe0 | c >> 12);
+        putchar(0x80 | (c >> 6 & 0x3f));
+        putchar(0x80 | (c & 0x3f));
+    } else {
+        putchar(0xf0 | c >> 18);
+        putchar(0x80 | (c >> 12 & 0x3f));
+        putchar(0x80 | (c >> 6 & 0x3f));
+        putchar(0x80 | (c & 0x3f));
+    }
+}
+
+",
+        );
+    }
+    let put = if cell_size == CellSize::U8 {
+        "putchar"
+    } else {
+        "put"
+    };
+    let on_eof = match eof {
+        Eof::Zero => " else *p = 0;",
+        Eof::NegOne => " else *p = -1;",
+        Eof::Unchanged => "",
+    };
+
+    c.push_str("int main(void) {\n");
+    let _ = writeln!(c, "    {} *p = tape;", cell_type);
+    if op_list.ops.contains(&Op::In) {
+        c.push_str("    int c;\n");
+    }
+
+    // a compiled list is already optimized, possibly at level 2
+    let op_list = optimize::optimize(op_list, 2);
+    let mut depth = 1;
+    for (i, &op) in op_list.ops.iter().enumerate() {
+        if op == Op::Close {
+            depth -= 1;
+        }
+        let indent = "    ".repeat(depth);
+        let n = op_list.count(i);
+        let _ = match op {
+            Op::Left | Op::Right | Op::Incr | Op::Decr if n > 1 => {
+                let statement = match op {
+                    Op::Left => "p -=",
+                    Op::Right => "p +=",
+                    Op::Incr => "*p +=",
+                    _ => "*p -=",
+                };
+                writeln!(c, "{}{} {};", indent, statement, n)
+            }
+            Op::Left => writeln!(c, "{}p--;", indent),
+            Op::Right => writeln!(c, "{}p++;", indent),
+            Op::Incr => writeln!(c, "{}(*p)++;", indent),
+            Op::Decr => writeln!(c, "{}(*p)--;", indent),
+            Op::Out => writeln!(c, "{}{}(*p);", indent, put),
+            Op::In => writeln!(c, "{}if ((c = getchar()) != EOF) *p = c;{}", indent, on_eof),
+            Op::Open => writeln!(c, "{}while (*p) {{", indent),
+            Op::Close => writeln!(c, "{}}}", indent),
+            Op::Clear => writeln!(c, "{}*p = 0;", indent),
+            Op::MulAdd => {
+                let (offset, factor) = op_list.target(i);
+                writeln!(c, "{}p[{}] += *p * {}u;", indent, offset, factor)
+            }
+            Op::Debug | Op::DumpTape | Op::PrintPtr => Ok(()),
+        };
+        if op == Op::Open {
+            depth += 1;
+        }
+    }
+
+    c.push_str("    return 0;\n}\n");
+    c
+}
+
+/// Indentation of a loop body in `format_program`.
+const INDENT: &str = "    ";
+
+/// Writes the program back without comments, with every bracket on its own
+/// line and loop bodies indented, e.g. `+[->+<]` becomes `+`, `[`, `->+<`
+/// (indented) and `]` on four lines.
+pub fn format_program(ops: &[Op]) -> String {
+    let mut program = String::new();
+    let mut line = String::new();
+    let mut depth = 0;
+    for &op in ops {
+        if op != Op::Open && op != Op::Close {
+            line.push_str(&op.to_string());
+            continue;
+        }
+        if !line.is_empty() {
+            program.push_str(&INDENT.repeat(depth));
+            program.push_str(&line);
+            program.push('\n');
+            line.clear();
+        }
+        if op == Op::Close {
+            depth -= 1;
+        }
+        program.push_str(&INDENT.repeat(depth));
+        program.push_str(&op.to_string());
+        program.push('\n');
+        if op == Op::Open {
+            depth += 1;
+        }
+    }
+    if !line.is_empty() {
+        program.push_str(&line);
+        program.push('\n');
+    }
+    program
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hello_world_keeps_its_loops() {
+        let hello = include_str!("../examples/helloworld.b");
+        let c = c_program(
+            OpList::new(hello).unwrap(),
+            CellSize::U8,
+            Eof::Unchanged,
+            30000,
+        );
+
+        assert!(c.contains("static uint8_t tape[30000];"));
+        assert!(c.contains("int main(void) {\n    uint8_t *p = tape;\n"));
+        // the loop moving the cell to four others is replaced
+        assert!(c.contains("    while (*p) {\n        p++;\n        *p += 4;\n"));
+        assert!(c.contains("        p[1] += *p * 2u;\n"));
+        assert!(c.contains("        while (*p) {\n            p--;\n        }\n"));
+        assert_eq!(c.matches("while (*p) {").count(), 2);
+        assert!(c.contains("putchar(*p);"));
+        assert!(c.contains("*p += 8;"));
+        assert!(c.ends_with("    return 0;\n}\n"));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{ErrorKind, Op, OpList};
+
+const MAGIC: &[u8; 4] = b"BFRS";
+/// Bumped every time the layout changes so stale files are rejected.
+const VERSION: u8 = 2;
+
+/// Byte of the ops that have no source char.
+const CLEAR: u8 = b'0';
+const MUL_ADD: u8 = b'*';
+
+/// Serializes the optimized program so loading it doesn't optimize it
+/// again: magic, version, optimization level, number of ops (u64 LE), then
+/// for every op its char and count, followed for a `MulAdd` by the offset
+/// (i64 LE) and factor (u32 LE) of its target.
+pub fn save(op_list: &OpList) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 10 + op_list.ops.len() * 2);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.push(op_list.level());
+    bytes.extend_from_slice(&(op_list.ops.len() as u64).to_le_bytes());
+    for (i, &op) in op_list.ops.iter().enumerate() {
+        bytes.push(match op.to_char() {
+            Some(c) => c as u8,
+            None if op == Op::Clear => CLEAR,
+            None => MUL_ADD,
+        });
+        bytes.push(op_list.count(i) as u8);
+        if op == Op::MulAdd {
+            let (offset, factor) = op_list.target(i);
+            bytes.extend_from_slice(&(offset as i64).to_le_bytes());
+            bytes.extend_from_slice(&factor.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+pub fn load(bytes: &[u8]) -> Result<OpList, ErrorKind> {
+    let bytes = bytes
+        .strip_prefix(MAGIC)
+        .ok_or(ErrorKind::InvalidCompiled("not a compiled program"))?;
+    let (&version, bytes) = bytes
+        .split_first()
+        .ok_or(ErrorKind::InvalidCompiled("missing version"))?;
+    if version != VERSION {
+        return Err(ErrorKind::InvalidCompiled(
+            "compiled with another version, compile it again",
+        ));
+    }
+    let (&level, bytes) = bytes
+        .split_first()
+        .ok_or(ErrorKind::InvalidCompiled("missing optimization level"))?;
+
+    let (len, mut bytes) = bytes
+        .split_first_chunk::<8>()
+        .ok_or(ErrorKind::InvalidCompiled("missing length"))?;
+    let len = u64::from_le_bytes(*len);
+
+    const TRUNCATED: ErrorKind = ErrorKind::InvalidCompiled("truncated");
+    let mut ops = Vec::new();
+    let mut counts = Vec::new();
+    let mut targets = Vec::new();
+    for _ in 0..len {
+        let ([op, count], rest) = bytes.split_first_chunk().ok_or(TRUNCATED)?;
+        let op = match *op {
+            CLEAR => Op::Clear,
+            MUL_ADD => Op::MulAdd,
+            b => Op::try_from_extended_char(b as char)
+                .ok_or(ErrorKind::InvalidCompiled("unknown op"))?,
+        };
+        bytes = rest;
+        if op == Op::MulAdd {
+            let (offset, rest) = bytes.split_first_chunk::<8>().ok_or(TRUNCATED)?;
+            let (factor, rest) = rest.split_first_chunk::<4>().ok_or(TRUNCATED)?;
+            targets.push((
+                i64::from_le_bytes(*offset) as isize,
+                u32::from_le_bytes(*factor),
+            ));
+            bytes = rest;
+        }
+        ops.push(op);
+        counts.push(*count);
+    }
+    if !bytes.is_empty() {
+        return Err(ErrorKind::InvalidCompiled("trailing bytes"));
+    }
+
+    Ok(OpList::from_parts(ops, counts, targets, level)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize;
+
+    #[test]
+    fn round_trip_keeps_the_optimized_list() {
+        let program = "++++++[>++++++++<-]>[-]+++.[->+>++<<],";
+        let op_list = optimize::optimize(OpList::new(program).unwrap(), 2);
+        let loaded = load(&save(&op_list)).unwrap();
+
+        assert_eq!(loaded.level(), 2);
+        assert_eq!(loaded.ops, op_list.ops);
+        for i in 0..op_list.ops.len() {
+            assert_eq!(loaded.count(i), op_list.count(i));
+            match op_list.ops[i] {
+                Op::MulAdd => assert_eq!(loaded.target(i), op_list.target(i)),
+                Op::Open | Op::Close => assert_eq!(loaded.matching(i), op_list.matching(i)),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let op_list = OpList::new("+[-]").unwrap().folded();
+        let bytes = save(&op_list);
+        assert!(matches!(
+            load(&bytes[..bytes.len() - 1]),
+            Err(ErrorKind::InvalidCompiled("truncated"))
+        ));
+    }
+}
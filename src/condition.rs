@@ -0,0 +1,57 @@
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A predicate on the value of a cell, written as a comparison operator
+/// followed by a value, e.g. `==0`, `!=0` or `>=100`.
+#[derive(Debug, Clone, Copy)]
+pub struct CellCondition {
+    comparison: Comparison,
+    value: u32,
+}
+
+impl CellCondition {
+    pub fn matches(&self, cell: u32) -> bool {
+        match self.comparison {
+            Comparison::Eq => cell == self.value,
+            Comparison::Ne => cell != self.value,
+            Comparison::Lt => cell < self.value,
+            Comparison::Le => cell <= self.value,
+            Comparison::Gt => cell > self.value,
+            Comparison::Ge => cell >= self.value,
+        }
+    }
+}
+
+impl FromStr for CellCondition {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // two-char operators first so `<=` isn't read as `<`
+        const COMPARISONS: &[(&str, Comparison)] = &[
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+            ("=", Comparison::Eq),
+        ];
+
+        let &(op, comparison) = COMPARISONS
+            .iter()
+            .find(|(op, _)| s.starts_with(op))
+            .ok_or(())?;
+        let value = s[op.len()..].trim().parse().map_err(|_| ())?;
+
+        Ok(CellCondition { comparison, value })
+    }
+}
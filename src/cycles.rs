@@ -0,0 +1,51 @@
+use crate::{ErrorKind, Op};
+
+/// Weight of each op type, used to compute a synthetic "cycles" metric that
+/// is more representative than the raw op count.
+pub struct CostModel {
+    weights: [u64; Op::COUNT],
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        let mut weights = [1; Op::COUNT];
+        weights[Op::Out.index()] = 10;
+        weights[Op::In.index()] = 10;
+        weights[Op::Open.index()] = 2;
+        weights[Op::Close.index()] = 2;
+        CostModel { weights }
+    }
+}
+
+impl CostModel {
+    /// Parses a cost model file: one `<op> <weight>` pair per line, empty
+    /// lines and lines starting with `#` are ignored. Ops that aren't listed
+    /// keep their default weight.
+    pub fn parse(s: &str) -> Result<Self, ErrorKind> {
+        let mut model = CostModel::default();
+
+        for (line_i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let (Some(op), Some(weight), None) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(ErrorKind::ParseCostModel(line_i + 1));
+            };
+            let op = op
+                .parse::<Op>()
+                .map_err(|_| ErrorKind::ParseCostModel(line_i + 1))?;
+            model.weights[op.index()] = weight
+                .parse()
+                .map_err(|_| ErrorKind::ParseCostModel(line_i + 1))?;
+        }
+
+        Ok(model)
+    }
+
+    pub fn weight(&self, op: Op) -> u64 {
+        self.weights[op.index()]
+    }
+}
@@ -0,0 +1,1384 @@
+pub mod optimize;
+pub mod parse;
+
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use parse::{ParseError, ParsedProgram};
+
+/// Location of an instruction in the source file (1-based).
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// Number of bits of each cell, values wrap around at the matching bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellSize {
+    #[default]
+    U8,
+    U16,
+    U32,
+}
+
+impl CellSize {
+    /// Largest value a cell can hold, also used as a mask to wrap values.
+    pub fn max(self) -> u32 {
+        match self {
+            CellSize::U8 => u8::MAX as u32,
+            CellSize::U16 => u16::MAX as u32,
+            CellSize::U32 => u32::MAX,
+        }
+    }
+
+    /// Reinterprets a cell as a two's complement signed value.
+    pub fn to_signed(self, v: u32) -> i32 {
+        match self {
+            CellSize::U8 => v as u8 as i8 as i32,
+            CellSize::U16 => v as u16 as i16 as i32,
+            CellSize::U32 => v as i32,
+        }
+    }
+}
+
+impl FromStr for CellSize {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(CellSize::U8),
+            "16" => Ok(CellSize::U16),
+            "32" => Ok(CellSize::U32),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What `,` writes to the cell once the input is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eof {
+    Zero,
+    /// The largest cell value, -1 as a signed cell.
+    NegOne,
+    #[default]
+    Unchanged,
+}
+
+impl FromStr for Eof {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(Eof::Zero),
+            "neg-one" | "minus-one" => Ok(Eof::NegOne),
+            "unchanged" => Ok(Eof::Unchanged),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How `.` writes the cell to the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// The cell as a byte, or a UTF-8 encoded char with wider cells.
+    #[default]
+    Char,
+    /// The decimal value of the cell followed by a space.
+    Numeric,
+    /// Like `Numeric` with the cell as a signed value, see
+    /// `CellSize::to_signed`.
+    SignedNumeric,
+    /// The lowest byte of the cell, whatever the cell size.
+    Byte,
+}
+
+#[derive(Clone)]
+pub struct Memory {
+    pub ptr: usize,
+    /// Cells are stored as `u32` whatever their size, `cell_size` decides
+    /// where they wrap. A single tape type for every cell size keeps the
+    /// code reading cells (the preview, the debugger...) independent of it,
+    /// at the cost of 4 bytes per 8-bit cell.
+    pub data: Vec<u32>,
+    pub cell_size: CellSize,
+    /// Number of times the tape grew past its initial capacity.
+    pub growths: usize,
+    /// Number of cells the tape can't grow past.
+    pub limit: usize,
+    /// Moving left of the first cell goes to the last one, and with a limit
+    /// moving right of the last cell goes to the first one.
+    pub wrap: bool,
+    /// Highest cell the pointer reached.
+    pub max_ptr: usize,
+    /// Moving left of the first cell adds cells before it instead of
+    /// failing, see `origin`.
+    pub infinite: bool,
+    /// Index in `data` of the cell the pointer started at, cells added on the
+    /// left have negative addresses (see `address`).
+    pub origin: usize,
+}
+
+impl Memory {
+    pub const DEFAULT_MEMORY_CAPACITY: usize = 65536;
+
+    pub fn new() -> Self {
+        Self::with_cell_size(CellSize::U8)
+    }
+
+    pub fn with_cell_size(cell_size: CellSize) -> Self {
+        Memory {
+            ptr: 0,
+            data: vec![0; Self::DEFAULT_MEMORY_CAPACITY],
+            cell_size,
+            growths: 0,
+            limit: usize::MAX,
+            wrap: false,
+            max_ptr: 0,
+            infinite: false,
+            origin: 0,
+        }
+    }
+
+    /// Replaces the tape with an empty one of `capacity` cells (at least
+    /// one, at most the limit), it still grows when the pointer goes past
+    /// it.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.data = vec![0; capacity.clamp(1, self.limit)];
+        self
+    }
+
+    /// Caps the tape to `limit` cells (at least one).
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self.data.truncate(self.limit);
+        self
+    }
+
+    /// Address of the cell at index `i` of `data`, relative to the cell the
+    /// pointer started at.
+    pub fn address(&self, i: usize) -> isize {
+        i as isize - self.origin as isize
+    }
+
+    /// Index in `data` of the cell at `address`, `None` for cells the tape
+    /// doesn't have (yet).
+    pub fn index_of(&self, address: isize) -> Option<usize> {
+        self.origin
+            .checked_add_signed(address)
+            .filter(|&i| i < self.data.len())
+    }
+
+    /// Address of the current cell, see `address`.
+    pub fn position(&self) -> isize {
+        self.address(self.ptr)
+    }
+
+    /// Index and value of every nonzero cell, in order.
+    pub fn nonzero_cells(&self) -> impl Iterator<Item = (usize, u32)> + '_ {
+        self.data
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, v)| v != 0)
+    }
+
+    #[inline]
+    pub fn read(&self) -> u32 {
+        self.data[self.ptr]
+    }
+    /// The value is wrapped to the cell size.
+    #[inline]
+    pub fn set(&mut self, v: u32) {
+        self.data[self.ptr] = v & self.cell_size.max()
+    }
+
+    #[inline]
+    pub fn left(&mut self) -> Result<(), TapeError> {
+        self.left_by(1)
+    }
+    /// Fails without moving when going left of the first cell, unless the
+    /// tape wraps or is infinite.
+    #[inline]
+    pub fn left_by(&mut self, n: usize) -> Result<(), TapeError> {
+        if self.ptr >= n {
+            self.ptr -= n;
+        } else if self.wrap {
+            let len = self.data.len();
+            self.ptr = (self.ptr + len - n % len) % len;
+            self.max_ptr = self.max_ptr.max(self.ptr);
+        } else if self.infinite {
+            self.grow_left(n - self.ptr)?;
+            self.ptr -= n;
+        } else {
+            return Err(TapeError::LeftEdge);
+        }
+        Ok(())
+    }
+    /// Adds at least `needed` cells before the first one, doubling the tape
+    /// like when growing right, up to the limit.
+    #[cold]
+    fn grow_left(&mut self, needed: usize) -> Result<(), TapeError> {
+        let len = self.data.len();
+        let mut added = len;
+        while added < needed {
+            added *= 2;
+        }
+        let added = added.min(self.limit - len);
+        if added < needed {
+            return Err(TapeError::Limit(self.limit));
+        }
+        self.data.splice(0..0, std::iter::repeat_n(0, added));
+        self.ptr += added;
+        self.max_ptr += added;
+        self.origin += added;
+        self.growths += 1;
+        Ok(())
+    }
+    /// Returns whether the tape had to grow.
+    #[inline]
+    pub fn right(&mut self) -> Result<bool, TapeError> {
+        self.right_by(1)
+    }
+    /// Returns whether the tape had to grow. The tape doubles every time so
+    /// long runs of moves don't keep reallocating, up to the limit. Fails
+    /// without moving when the limit would be exceeded.
+    #[inline]
+    pub fn right_by(&mut self, n: usize) -> Result<bool, TapeError> {
+        // the tape never exceeds the limit, it only has to be checked when
+        // leaving the tape
+        if self.ptr + n < self.data.len() {
+            self.ptr += n;
+            self.max_ptr = self.max_ptr.max(self.ptr);
+            return Ok(false);
+        }
+        self.grow_to(self.ptr + n)
+    }
+    #[cold]
+    fn grow_to(&mut self, ptr: usize) -> Result<bool, TapeError> {
+        self.ptr = match ptr {
+            // the wrapped cell can still be past the end of the tape when
+            // it hasn't grown to the limit yet
+            ptr if ptr >= self.limit && self.wrap => ptr % self.limit,
+            ptr if ptr >= self.limit => return Err(TapeError::Limit(self.limit)),
+            ptr => ptr,
+        };
+        self.max_ptr = self.max_ptr.max(self.ptr);
+        let growths = self.growths;
+        while self.ptr >= self.data.len() {
+            let len = (self.data.len() * 2).min(self.limit);
+            self.data.resize(len, 0);
+            self.growths += 1;
+        }
+        Ok(self.growths > growths)
+    }
+    /// Adds `v` to the cell `offset` cells away and comes back, moving like
+    /// `<` and `>` would so the tape grows the same way.
+    pub fn add_at(&mut self, offset: isize, v: u32) -> Result<(), TapeError> {
+        let n = offset.unsigned_abs();
+        if offset < 0 {
+            self.left_by(n)?;
+            self.add(v);
+            self.right_by(n).map(|_| ())
+        } else {
+            self.right_by(n)?;
+            self.add(v);
+            self.left_by(n)
+        }
+    }
+
+    #[inline]
+    pub fn incr(&mut self) {
+        self.add(1);
+    }
+    #[inline]
+    pub fn decr(&mut self) {
+        self.sub(1);
+    }
+    #[inline]
+    pub fn add(&mut self, n: u32) {
+        self.set(self.read().wrapping_add(n));
+    }
+    #[inline]
+    pub fn sub(&mut self, n: u32) {
+        self.set(self.read().wrapping_sub(n));
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A move that leaves the tape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapeError {
+    /// Moving right past the last cell the tape can grow to.
+    Limit(usize),
+    /// Moving left of the first cell.
+    LeftEdge,
+}
+
+impl Display for TapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TapeError::Limit(limit) => write!(f, "the tape is limited to {} cells", limit),
+            TapeError::LeftEdge => write!(f, "the pointer moved left of the first cell"),
+        }
+    }
+}
+
+/// A `[` or `]` without a matching bracket.
+#[derive(Debug, Clone, Copy)]
+pub struct UnmatchedBracket {
+    /// Index of the op in the program.
+    pub index: usize,
+    pub op: Op,
+}
+
+impl Display for UnmatchedBracket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.op {
+            Op::Open => write!(f, "unclosed `[` at op {}", self.index),
+            _ => write!(f, "unmatched `]` at op {}", self.index),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct OpList {
+    pub pos: usize,
+    pub ops: Vec<Op>,
+    /// Index of the matching bracket for every bracket, and of the target in
+    /// `targets` for every `MulAdd`. Unused for other ops.
+    jump: Vec<usize>,
+    /// Number of times each op is repeated, 1 unless the list was folded.
+    counts: Vec<u8>,
+    /// Offset and factor of each `MulAdd`, see `optimize::mul_loops`.
+    targets: Vec<(isize, u32)>,
+    /// Optimization level the list was built with, see `optimize::optimize`.
+    level: u8,
+}
+
+impl OpList {
+    pub fn new(operations: &str) -> Result<Self, UnmatchedBracket> {
+        OpList::from_ops(operations.chars().map(Op::from_char).collect())
+    }
+
+    /// Precomputes the jump table, fails if the brackets are unbalanced.
+    pub fn from_ops(ops: Vec<Op>) -> Result<Self, UnmatchedBracket> {
+        let mut jump = vec![0; ops.len()];
+        let mut open_brackets = Vec::new();
+        for (i, &op) in ops.iter().enumerate() {
+            match op {
+                Op::Open => open_brackets.push(i),
+                Op::Close => {
+                    let start = open_brackets.pop().ok_or(UnmatchedBracket {
+                        index: i,
+                        op: Op::Close,
+                    })?;
+                    jump[start] = i;
+                    jump[i] = start;
+                }
+                _ => {}
+            }
+        }
+        if let Some(&start) = open_brackets.first() {
+            return Err(UnmatchedBracket {
+                index: start,
+                op: Op::Open,
+            });
+        }
+
+        Ok(OpList {
+            pos: 0,
+            counts: vec![1; ops.len()],
+            ops,
+            jump,
+            targets: Vec::new(),
+            level: 0,
+        })
+    }
+
+    /// Builds an optimized list from its ops, the count of every op and the
+    /// offset and factor of every `MulAdd` in order, e.g. to load one that
+    /// was saved. Fails if the brackets are unbalanced.
+    pub fn from_parts(
+        ops: Vec<Op>,
+        counts: Vec<u8>,
+        targets: Vec<(isize, u32)>,
+        level: u8,
+    ) -> Result<Self, UnmatchedBracket> {
+        let mut op_list = OpList::from_ops(ops)?;
+        // the jump table is unused for `MulAdd`, it holds the target instead
+        let mul_adds = op_list
+            .ops
+            .iter()
+            .enumerate()
+            .filter(|(_, &op)| op == Op::MulAdd);
+        for (target_i, (i, _)) in mul_adds.enumerate() {
+            op_list.jump[i] = target_i;
+        }
+        Ok(OpList {
+            counts,
+            targets,
+            level,
+            ..op_list
+        })
+    }
+
+    /// Folds runs of the same `+`, `-`, `<` or `>` into a single op repeated
+    /// as many times (up to 255), so a run is executed in one step. Clear
+    /// loops (`[-]` and `[+]`) become a single `Clear`.
+    pub fn folded(self) -> Self {
+        let mut ops = Vec::new();
+        let mut counts = Vec::<u8>::new();
+        for op in self.ops {
+            if op == Op::Close
+                && ops.len() >= 2
+                && ops[ops.len() - 2] == Op::Open
+                && matches!(ops[ops.len() - 1], Op::Incr | Op::Decr)
+                && counts[counts.len() - 1] == 1
+            {
+                ops.truncate(ops.len() - 2);
+                counts.truncate(counts.len() - 2);
+                ops.push(Op::Clear);
+                counts.push(1);
+                continue;
+            }
+
+            match (ops.last(), counts.last_mut()) {
+                (Some(&last), Some(count))
+                    if last == op
+                        && matches!(op, Op::Incr | Op::Decr | Op::Left | Op::Right)
+                        && *count < u8::MAX =>
+                {
+                    *count += 1
+                }
+                _ => {
+                    ops.push(op);
+                    counts.push(1);
+                }
+            }
+        }
+
+        let op_list = OpList::from_ops(ops)
+            .unwrap_or_else(|_| unreachable!("folding unbalanced the brackets"));
+        OpList {
+            counts,
+            level: 1,
+            ..op_list
+        }
+    }
+
+    /// Reads the ops in chunks, unlike `new` the whole source is never held
+    /// in memory. Applies the same filtering as the default loader
+    /// (comments and unknown characters).
+    pub fn read_ops(mut reader: impl Read) -> io::Result<Vec<Op>> {
+        let mut ops = Vec::new();
+
+        let mut buf = vec![0; 1 << 16];
+        // 0: code, 1: after a `/`, 2: comment (see `parse::strip_comments`)
+        let mut line_state = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            for &b in &buf[..n] {
+                line_state = match (line_state, b) {
+                    (_, b'\n') => 0,
+                    (2, _) => 2,
+                    (_, b';') => 2,
+                    (0, b'/') => 1,
+                    (1, b'/') => 2,
+                    _ => 0,
+                };
+                // instructions are all ascii, so multi-byte utf-8 chars
+                // never match
+                if line_state == 0 {
+                    if let Some(op) = Op::try_from_char(b as char) {
+                        ops.push(op);
+                    }
+                }
+            }
+        }
+
+        Ok(ops)
+    }
+
+    #[inline]
+    pub fn get(&self) -> Op {
+        self.ops[self.pos]
+    }
+
+    /// Number of times the op at `i` is repeated.
+    #[inline]
+    pub fn count(&self, i: usize) -> u32 {
+        self.counts[i] as u32
+    }
+
+    /// Index of the bracket matching the one at `i`.
+    #[inline]
+    pub fn matching(&self, i: usize) -> usize {
+        self.jump[i]
+    }
+
+    /// Offset of the cell the `MulAdd` at `i` adds to and the factor the
+    /// current cell is multiplied by.
+    #[inline]
+    pub fn target(&self, i: usize) -> (isize, u32) {
+        self.targets[self.jump[i]]
+    }
+
+    /// Number of loops enclosing the current op, a `[` isn't inside its own
+    /// loop but a `]` is.
+    pub fn depth(&self) -> usize {
+        self.ops[..self.pos.min(self.ops.len())]
+            .iter()
+            .fold(0, |depth, op| match op {
+                Op::Open => depth + 1,
+                Op::Close => depth - 1,
+                _ => depth,
+            })
+    }
+
+    /// Optimization level the list was built with, see `optimize::optimize`.
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.pos >= self.ops.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Left,
+    Right,
+    Incr,
+    Decr,
+    Out,
+    In,
+    Open,
+    Close,
+    /// `#`, pauses execution when debugging and does nothing otherwise.
+    Debug,
+    /// `$`, extended: prints the nonzero cells. Does nothing in the
+    /// interpreter, it is left to the caller.
+    DumpTape,
+    /// `!`, extended: prints the pointer and the current cell. Does nothing
+    /// in the interpreter, it is left to the caller.
+    PrintPtr,
+    /// Sets the cell to 0, replaces `[-]` and `[+]` in folded op lists.
+    Clear,
+    /// Adds the current cell times a factor to another cell, part of a
+    /// multiplication loop replaced by `optimize::mul_loops`.
+    MulAdd,
+}
+
+impl Op {
+    /// Number of op types, `Clear` and `MulAdd` count as a `[`.
+    pub const COUNT: usize = 11;
+
+    /// Every op type, in index order.
+    pub const ALL: [Op; Op::COUNT] = [
+        Op::Left,
+        Op::Right,
+        Op::Incr,
+        Op::Decr,
+        Op::Out,
+        Op::In,
+        Op::Open,
+        Op::Close,
+        Op::Debug,
+        Op::DumpTape,
+        Op::PrintPtr,
+    ];
+
+    /// Index of the op type, for tables indexed by op type. `Clear` and
+    /// `MulAdd` share the index of `[` since they stand for a whole loop.
+    pub fn index(self) -> usize {
+        match self {
+            Op::Clear | Op::MulAdd => Op::Open as usize,
+            op => op as usize,
+        }
+    }
+
+    pub fn from_char(c: char) -> Self {
+        Self::try_from_char(c)
+            .unwrap_or_else(|| unreachable!("string contains illegal characters ({})", c))
+    }
+
+    pub fn try_from_char(c: char) -> Option<Self> {
+        match c {
+            '<' => Some(Op::Left),
+            '>' => Some(Op::Right),
+            '+' => Some(Op::Incr),
+            '-' => Some(Op::Decr),
+            '.' => Some(Op::Out),
+            ',' => Some(Op::In),
+            '[' => Some(Op::Open),
+            ']' => Some(Op::Close),
+            '#' => Some(Op::Debug),
+            _ => None,
+        }
+    }
+
+    /// Like `try_from_char`, also recognizes the extended ops (`$` and `!`).
+    pub fn try_from_extended_char(c: char) -> Option<Self> {
+        match c {
+            '$' => Some(Op::DumpTape),
+            '!' => Some(Op::PrintPtr),
+            c => Self::try_from_char(c),
+        }
+    }
+
+    /// Char of the op in the source, `None` for `Clear` and `MulAdd` which
+    /// don't appear in sources (`Display` shows them as `[-]` and `[*]`).
+    pub fn to_char(self) -> Option<char> {
+        Some(match self {
+            Op::Left => '<',
+            Op::Right => '>',
+            Op::Incr => '+',
+            Op::Decr => '-',
+            Op::Out => '.',
+            Op::In => ',',
+            Op::Open => '[',
+            Op::Close => ']',
+            Op::Debug => '#',
+            Op::DumpTape => '$',
+            Op::PrintPtr => '!',
+            Op::Clear | Op::MulAdd => return None,
+        })
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self, self.to_char()) {
+            (_, Some(c)) => write!(f, "{}", c),
+            (Op::Clear, None) => write!(f, "[-]"),
+            _ => write!(f, "[*]"),
+        }
+    }
+}
+
+impl FromStr for Op {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Op::try_from_extended_char(c).ok_or(()),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The op a step executed and where it left the pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo {
+    /// Index of the op in the op list.
+    pub index: usize,
+    pub op: Op,
+    /// Number of times the op was repeated, 1 unless the list was folded.
+    pub count: u32,
+    /// Pointer after the op.
+    pub ptr: usize,
+}
+
+impl StepInfo {
+    /// Whether the op wrote to the output.
+    pub fn wrote_output(&self) -> bool {
+        self.op == Op::Out
+    }
+}
+
+/// What a single step did.
+#[derive(Debug)]
+pub enum StepOutcome {
+    /// An op was executed.
+    Continue(StepInfo),
+    /// The end of the program was reached, nothing was executed.
+    Finished,
+    /// Reading input or writing output failed, the op wasn't executed.
+    Error(io::Error),
+    /// The pointer would leave the tape, the op wasn't executed.
+    Tape(TapeError),
+}
+
+/// Why execution stopped before the end of the program.
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// The pointer would leave the tape.
+    Tape(TapeError),
+    /// Reading input or writing output failed.
+    Io(io::Error),
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::Tape(e) => write!(f, "{}", e),
+            RuntimeError::Io(e) => write!(f, "I/O failed: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(e: io::Error) -> Self {
+        RuntimeError::Io(e)
+    }
+}
+
+impl From<TapeError> for RuntimeError {
+    fn from(e: TapeError) -> Self {
+        RuntimeError::Tape(e)
+    }
+}
+
+/// Number of steps performed by `Interpreter::run`.
+pub type RunResult = Result<usize, RuntimeError>;
+
+/// Executes a program one op at a time. Input is read byte by byte for `,`
+/// (once it is exhausted the cell is left unchanged, see `with_eof`) and
+/// every `.` writes a byte, or with cells wider than 8 bits the UTF-8
+/// encoding of the char (the low byte when the value isn't a char, see
+/// `with_output_format` for decimal values instead). The output is flushed
+/// before every `,`. Input and output default to empty ones, see
+/// `with_input` and `with_output`.
+pub struct Interpreter<R = io::Empty, W = io::Sink> {
+    pub mem: Memory,
+    pub op_list: OpList,
+    eof: Eof,
+    output_format: OutputFormat,
+    /// With numeric output, `.` writes nothing for a zero cell.
+    skip_zero: bool,
+    input: R,
+    output: W,
+}
+
+impl Interpreter {
+    pub fn new(program: &str) -> Result<Self, ParseError> {
+        let ParsedProgram { ops, .. } = parse::parse(program)?;
+        // brackets were checked by the parser
+        let op_list = OpList::from_ops(ops)
+            .unwrap_or_else(|_| unreachable!("parsed program has unmatched brackets"));
+        Ok(Interpreter::from_op_list(op_list))
+    }
+
+    pub fn from_op_list(op_list: OpList) -> Self {
+        Interpreter {
+            mem: Memory::new(),
+            op_list,
+            eof: Eof::default(),
+            output_format: OutputFormat::default(),
+            skip_zero: false,
+            input: io::empty(),
+            output: io::sink(),
+        }
+    }
+}
+
+impl<R: Read, W: Write> Interpreter<R, W> {
+    pub fn with_input<I: Read>(self, input: I) -> Interpreter<I, W> {
+        Interpreter {
+            mem: self.mem,
+            op_list: self.op_list,
+            eof: self.eof,
+            output_format: self.output_format,
+            skip_zero: self.skip_zero,
+            input,
+            output: self.output,
+        }
+    }
+
+    /// Replaces the memory with an empty one of the given cell size, keeps
+    /// the limit, wrapping and infinite tape.
+    pub fn with_cell_size(mut self, cell_size: CellSize) -> Self {
+        let mut mem = Memory::with_cell_size(cell_size).with_limit(self.mem.limit);
+        mem.wrap = self.mem.wrap;
+        mem.infinite = self.mem.infinite;
+        self.mem = mem;
+        self
+    }
+
+    /// Makes the tape circular, see `Memory::wrap`.
+    pub fn with_wrapping_tape(mut self) -> Self {
+        self.mem.wrap = true;
+        self
+    }
+
+    /// Lets the pointer move left of the first cell, see `Memory::infinite`.
+    pub fn with_infinite_tape(mut self) -> Self {
+        self.mem.infinite = true;
+        self
+    }
+
+    /// Sets the number of cells the tape starts with, see
+    /// `Memory::with_capacity`.
+    pub fn with_memory_capacity(mut self, capacity: usize) -> Self {
+        self.mem = std::mem::take(&mut self.mem).with_capacity(capacity);
+        self
+    }
+
+    /// Limits the tape to the given number of cells, see `Memory::with_limit`.
+    pub fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.mem = std::mem::take(&mut self.mem).with_limit(limit);
+        self
+    }
+
+    /// Sets what `,` does once the input is exhausted.
+    pub fn with_eof(mut self, eof: Eof) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// With `OutputFormat::Numeric` and `SignedNumeric`, `.` writes nothing
+    /// for a zero cell instead of `0`.
+    pub fn with_zero_skipped(mut self) -> Self {
+        self.skip_zero = true;
+        self
+    }
+
+    pub fn with_output<O: Write>(self, output: O) -> Interpreter<R, O> {
+        Interpreter {
+            mem: self.mem,
+            op_list: self.op_list,
+            eof: self.eof,
+            output_format: self.output_format,
+            skip_zero: self.skip_zero,
+            input: self.input,
+            output,
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    pub fn into_output(self) -> W {
+        self.output
+    }
+
+    /// Executes the current op and moves to the next one.
+    pub fn step(&mut self) -> StepOutcome {
+        if self.op_list.is_finished() {
+            return StepOutcome::Finished;
+        }
+
+        let index = self.op_list.pos;
+        let op = self.op_list.get();
+        let count = self.op_list.count(index);
+        match op {
+            Op::Left => {
+                if let Err(e) = self.mem.left_by(count as usize) {
+                    return StepOutcome::Tape(e);
+                }
+            }
+            Op::Right => {
+                if let Err(e) = self.mem.right_by(count as usize) {
+                    return StepOutcome::Tape(e);
+                }
+            }
+            Op::Incr => self.mem.add(count),
+            Op::Decr => self.mem.sub(count),
+            Op::Out => {
+                let v = self.mem.read();
+                let written = match self.output_format {
+                    OutputFormat::Numeric | OutputFormat::SignedNumeric
+                        if v == 0 && self.skip_zero =>
+                    {
+                        Ok(())
+                    }
+                    OutputFormat::Numeric => write!(self.output, "{} ", v),
+                    OutputFormat::SignedNumeric => {
+                        write!(self.output, "{} ", self.mem.cell_size.to_signed(v))
+                    }
+                    OutputFormat::Byte => self.output.write_all(&[v as u8]),
+                    OutputFormat::Char => {
+                        let mut buf = [0; 4];
+                        let bytes = match char::from_u32(v) {
+                            Some(c) if self.mem.cell_size != CellSize::U8 => {
+                                c.encode_utf8(&mut buf).as_bytes()
+                            }
+                            _ => {
+                                buf[0] = v as u8;
+                                &buf[..1]
+                            }
+                        };
+                        self.output.write_all(bytes)
+                    }
+                };
+                if let Err(e) = written {
+                    return StepOutcome::Error(e);
+                }
+            }
+            Op::In => {
+                // so a prompt written before is shown before waiting for input
+                if let Err(e) = self.output.flush() {
+                    return StepOutcome::Error(e);
+                }
+                let mut byte = [0];
+                loop {
+                    match self.input.read(&mut byte) {
+                        Ok(0) => {
+                            match self.eof {
+                                Eof::Zero => self.mem.set(0),
+                                Eof::NegOne => self.mem.set(self.mem.cell_size.max()),
+                                Eof::Unchanged => {}
+                            }
+                            break;
+                        }
+                        Ok(_) => {
+                            self.mem.set(byte[0] as u32);
+                            break;
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return StepOutcome::Error(e),
+                    }
+                }
+            }
+            Op::Open if self.mem.read() == 0 => {
+                self.op_list.pos = self.op_list.matching(self.op_list.pos)
+            }
+            Op::Clear => self.mem.set(0),
+            Op::MulAdd => {
+                if let Err(e) = self.mul_add(index) {
+                    return StepOutcome::Tape(e);
+                }
+            }
+            Op::Close if self.mem.read() != 0 => {
+                self.op_list.pos = self.op_list.matching(self.op_list.pos)
+            }
+            _ => {}
+        }
+
+        self.op_list.pos += 1;
+        StepOutcome::Continue(StepInfo {
+            index,
+            op,
+            count,
+            ptr: self.mem.ptr,
+        })
+    }
+
+    /// Executes the `MulAdd` at `index`, kept out of `step` so it doesn't
+    /// slow down the other ops.
+    #[inline(never)]
+    fn mul_add(&mut self, index: usize) -> Result<(), TapeError> {
+        let v = self.mem.read();
+        if v == 0 {
+            return Ok(());
+        }
+        let (offset, factor) = self.op_list.target(index);
+        self.mem.add_at(offset, v.wrapping_mul(factor))
+    }
+
+    /// Iterates over the steps until the end of the program, an error ends
+    /// the iteration.
+    pub fn steps(&mut self) -> Steps<'_, R, W> {
+        Steps {
+            interpreter: self,
+            done: false,
+        }
+    }
+
+    /// Steps until the end of the program, then flushes the output.
+    pub fn run(&mut self) -> RunResult {
+        let mut steps = 0;
+        loop {
+            match self.step() {
+                StepOutcome::Continue(_) => steps += 1,
+                StepOutcome::Finished => break,
+                StepOutcome::Error(e) => return Err(e.into()),
+                StepOutcome::Tape(e) => return Err(e.into()),
+            }
+        }
+        self.output.flush()?;
+        Ok(steps)
+    }
+}
+
+/// Everything that changes while an interpreter runs, see
+/// `Interpreter::snapshot`.
+#[derive(Clone)]
+pub struct State<R, W> {
+    pub mem: Memory,
+    /// Position in the op list.
+    pub pos: usize,
+    /// What is left of the input.
+    pub input: R,
+    /// The output written so far.
+    pub output: W,
+}
+
+impl<R: Read + Clone, W: Write + Clone> Interpreter<R, W> {
+    /// Captures the state, with input and output that can be cloned (e.g.
+    /// `&[u8]` and `Vec<u8>`) so it can be restored later.
+    pub fn snapshot(&self) -> State<R, W> {
+        State {
+            mem: self.mem.clone(),
+            pos: self.op_list.pos,
+            input: self.input.clone(),
+            output: self.output.clone(),
+        }
+    }
+
+    /// Goes back to a state captured by `snapshot`, the op list stays the
+    /// same.
+    pub fn restore(&mut self, state: State<R, W>) {
+        self.mem = state.mem;
+        self.op_list.pos = state.pos;
+        self.input = state.input;
+        self.output = state.output;
+    }
+}
+
+/// Steps of an interpreter, see `Interpreter::steps`.
+pub struct Steps<'a, R, W> {
+    interpreter: &'a mut Interpreter<R, W>,
+    done: bool,
+}
+
+impl<R: Read, W: Write> Iterator for Steps<'_, R, W> {
+    type Item = Result<StepInfo, RuntimeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.interpreter.step() {
+            StepOutcome::Continue(info) => Some(Ok(info)),
+            StepOutcome::Finished => None,
+            StepOutcome::Error(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+            StepOutcome::Tape(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrapping_past_the_limit_grows_to_the_wrapped_cell() {
+        let mut mem = Memory::new().with_limit(20).with_capacity(4);
+        mem.wrap = true;
+        mem.right_by(29).unwrap();
+        assert_eq!(mem.ptr, 9);
+        assert_eq!(mem.max_ptr, 9);
+        assert!(mem.data.len() > 9);
+        mem.incr();
+        assert_eq!(mem.read(), 1);
+    }
+
+    #[test]
+    fn small_capacity_grows() {
+        let mut mem = Memory::new().with_capacity(2);
+        assert!(!mem.right_by(1).unwrap());
+        assert!(mem.right_by(1).unwrap());
+        assert_eq!(mem.data.len(), 4);
+        assert_eq!(mem.growths, 1);
+        assert_eq!(mem.read(), 0);
+    }
+
+    fn output_of(program: &str, output_format: OutputFormat) -> String {
+        let mut interpreter = Interpreter::new(program)
+            .unwrap()
+            .with_output_format(output_format)
+            .with_output(Vec::new());
+        interpreter.run().unwrap();
+        String::from_utf8(interpreter.into_output()).unwrap()
+    }
+
+    #[test]
+    fn signed_numeric_output() {
+        assert_eq!(output_of("-.+.", OutputFormat::SignedNumeric), "-1 0 ");
+        assert_eq!(output_of("-.+.", OutputFormat::Numeric), "255 0 ");
+    }
+
+    #[test]
+    fn clear_loop_takes_one_step() {
+        let program = format!("{}[-]", "+".repeat(200));
+        let mut interpreter = Interpreter::from_op_list(OpList::new(&program).unwrap().folded());
+        assert_eq!(interpreter.op_list.ops, [Op::Incr, Op::Clear]);
+        assert_eq!(interpreter.run().unwrap(), 2);
+        assert_eq!(interpreter.mem.read(), 0);
+        assert_eq!(Op::Clear.to_char(), None);
+        assert_eq!(Op::Clear.to_string(), "[-]");
+    }
+
+    #[test]
+    fn signed_numeric_output_of_zero_and_high_values() {
+        let program = format!("{}.{}.", "-".repeat(256), "+".repeat(200));
+        assert_eq!(output_of(&program, OutputFormat::SignedNumeric), "0 -56 ");
+        assert_eq!(output_of(&program, OutputFormat::Numeric), "0 200 ");
+    }
+
+    #[test]
+    fn every_optimization_level_gives_the_same_output() {
+        let hello = include_str!("../examples/helloworld.b");
+        let ops = parse::parse(hello).unwrap().ops;
+        let outputs = (0..=2)
+            .map(|level| {
+                let op_list = optimize::optimize(OpList::from_ops(ops.clone()).unwrap(), level);
+                let mut interpreter = Interpreter::from_op_list(op_list).with_output(Vec::new());
+                interpreter.run().unwrap();
+                interpreter.into_output()
+            })
+            .collect::<Vec<_>>();
+        assert!(outputs[0].starts_with(b"Hello World!"));
+        assert_eq!(outputs[1], outputs[0]);
+        assert_eq!(outputs[2], outputs[0]);
+    }
+
+    #[test]
+    fn expanded_folded_ops_parse_back_to_the_same_list() {
+        let program = "+++[->>--<<]>[-]<<<,+[+]..";
+        let folded = OpList::new(program).unwrap().folded();
+        let expanded = (0..folded.ops.len())
+            .map(|i| folded.ops[i].to_string().repeat(folded.count(i) as usize))
+            .collect::<String>();
+        let reparsed = OpList::new(&expanded).unwrap().folded();
+
+        assert_eq!(reparsed.ops, folded.ops);
+        for i in 0..folded.ops.len() {
+            assert_eq!(reparsed.count(i), folded.count(i));
+        }
+    }
+
+    #[test]
+    fn steps_then_runs_to_the_end() {
+        let mut interpreter = Interpreter::new("++>+.").unwrap().with_output(Vec::new());
+        assert!(matches!(
+            interpreter.step(),
+            StepOutcome::Continue(StepInfo {
+                index: 0,
+                op: Op::Incr,
+                ..
+            })
+        ));
+        assert_eq!(interpreter.mem.read(), 1);
+
+        assert_eq!(interpreter.run().unwrap(), 4);
+        assert_eq!(interpreter.mem.ptr, 1);
+        assert_eq!(interpreter.mem.data[..2], [2, 1]);
+        assert_eq!(interpreter.output(), &[1]);
+        assert!(matches!(interpreter.step(), StepOutcome::Finished));
+    }
+
+    #[test]
+    fn each_eof_mode_reading_past_the_input() {
+        let read_past = |eof| {
+            let mut interpreter = Interpreter::new(",>+,")
+                .unwrap()
+                .with_input(&b"a"[..])
+                .with_eof(eof);
+            interpreter.run().unwrap();
+            (interpreter.mem.data[0], interpreter.mem.read())
+        };
+        assert_eq!(read_past(Eof::Zero), (b'a' as u32, 0));
+        assert_eq!(read_past(Eof::NegOne), (b'a' as u32, 255));
+        assert_eq!(read_past(Eof::Unchanged), (b'a' as u32, 1));
+    }
+
+    #[test]
+    fn moving_to_the_default_capacity_gives_a_zero_cell() {
+        let mut mem = Memory::new();
+        mem.right_by(Memory::DEFAULT_MEMORY_CAPACITY - 1).unwrap();
+        assert!(mem.right().unwrap());
+        assert_eq!(mem.ptr, Memory::DEFAULT_MEMORY_CAPACITY);
+        assert_eq!(mem.read(), 0);
+        mem.incr();
+        assert_eq!(mem.read(), 1);
+        assert!(mem.data.len() > mem.ptr);
+    }
+
+    #[test]
+    fn overflowing_a_byte_depends_on_the_cell_size() {
+        let program = format!("{}.", "+".repeat(256));
+        let output_with = |cell_size| {
+            let mut interpreter = Interpreter::new(&program)
+                .unwrap()
+                .with_cell_size(cell_size)
+                .with_output(Vec::new());
+            interpreter.run().unwrap();
+            interpreter.into_output()
+        };
+        assert_eq!(output_with(CellSize::U8), [0]);
+        assert_eq!(output_with(CellSize::U16), "Ā".as_bytes());
+        assert_eq!(output_with(CellSize::U32), "Ā".as_bytes());
+    }
+
+    #[test]
+    fn unmatched_brackets_are_rejected_up_front() {
+        let e = OpList::new("+-]+").err().unwrap();
+        assert_eq!((e.index, e.op), (2, Op::Close));
+        assert_eq!(e.to_string(), "unmatched `]` at op 2");
+
+        let e = OpList::new("[+[-]").err().unwrap();
+        assert_eq!((e.index, e.op), (0, Op::Open));
+        assert_eq!(e.to_string(), "unclosed `[` at op 0");
+    }
+
+    #[test]
+    fn walking_past_the_limit_fails() {
+        let mut interpreter = Interpreter::new("+[>+]").unwrap();
+        interpreter.mem = Memory::new().with_limit(8);
+        assert!(matches!(
+            interpreter.run(),
+            Err(RuntimeError::Tape(TapeError::Limit(8)))
+        ));
+        assert_eq!(interpreter.mem.ptr, 7);
+    }
+
+    #[test]
+    fn left_of_the_first_cell_fails_or_wraps() {
+        let mut mem = Memory::new();
+        assert_eq!(mem.left(), Err(TapeError::LeftEdge));
+        assert_eq!(mem.ptr, 0);
+
+        let mut mem = Memory::new().with_limit(4);
+        mem.wrap = true;
+        mem.left().unwrap();
+        assert_eq!(mem.ptr, 3);
+        mem.right().unwrap();
+        assert_eq!(mem.ptr, 0);
+    }
+
+    #[test]
+    fn steps_yield_every_executed_op() {
+        let mut interpreter = Interpreter::new("+[>.<-]").unwrap();
+        let steps = interpreter
+            .steps()
+            .map(|step| step.map(|info| (info.index, info.op, info.ptr, info.wrote_output())))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            steps,
+            [
+                (0, Op::Incr, 0, false),
+                (1, Op::Open, 0, false),
+                (2, Op::Right, 1, false),
+                (3, Op::Out, 1, true),
+                (4, Op::Left, 0, false),
+                (5, Op::Decr, 0, false),
+                (6, Op::Close, 0, false),
+            ]
+        );
+
+        let mut interpreter = Interpreter::new("<").unwrap();
+        let mut steps = interpreter.steps();
+        assert!(matches!(
+            steps.next(),
+            Some(Err(RuntimeError::Tape(TapeError::LeftEdge)))
+        ));
+        assert!(steps.next().is_none());
+    }
+
+    #[test]
+    fn restoring_a_snapshot_replays_the_same_steps() {
+        let mut interpreter = Interpreter::new(",.,.,.")
+            .unwrap()
+            .with_input(&b"abc"[..])
+            .with_output(Vec::new());
+        interpreter.steps().take(3).for_each(drop);
+        let state = interpreter.snapshot();
+        assert_eq!(state.output, b"a");
+
+        // diverges from the snapshot
+        interpreter.mem.set(b'x' as u32);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.output(), b"axc");
+
+        // what was read after the snapshot is read again
+        interpreter.restore(state);
+        assert_eq!(interpreter.mem.read(), b'b' as u32);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.output(), b"abc");
+    }
+
+    #[test]
+    fn output_is_flushed_before_reading_input() {
+        use std::{cell::RefCell, rc::Rc};
+
+        /// Records the calls made to it in a log shared with the test.
+        struct Recorder(Rc<RefCell<Vec<&'static str>>>);
+
+        impl Write for Recorder {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().push("write");
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.0.borrow_mut().push("flush");
+                Ok(())
+            }
+        }
+
+        impl Read for Recorder {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.borrow_mut().push("read");
+                buf[0] = b'y';
+                Ok(1)
+            }
+        }
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new("+.,.")
+            .unwrap()
+            .with_input(Recorder(log.clone()))
+            .with_output(Recorder(log.clone()));
+        interpreter.run().unwrap();
+        assert_eq!(*log.borrow(), ["write", "flush", "read", "write", "flush"]);
+    }
+
+    #[test]
+    fn output_of_one_program_feeds_the_next() {
+        let mut hello = Interpreter::new("++++++++[>+++++++++<-]>.+++++++++++++++++++++++++++++.")
+            .unwrap()
+            .with_output(Vec::new());
+        hello.run().unwrap();
+
+        let cat = ",[.[-],]";
+        let mut cat = Interpreter::new(cat)
+            .unwrap()
+            .with_input(io::Cursor::new(hello.into_output()))
+            .with_output(Vec::new());
+        cat.run().unwrap();
+        assert_eq!(cat.into_output(), b"He");
+    }
+}
@@ -1,18 +1,72 @@
+mod analysis;
 mod cli;
+mod codegen;
+mod compiled;
+mod condition;
+mod cycles;
+mod preview;
+mod profile;
+mod repl;
+mod runlog;
+mod terminal;
+mod watch;
 
 use std::{
-    fmt::{Debug, Write},
+    collections::HashMap,
+    env,
+    fmt::Debug,
     fs,
-    io::stdin,
+    io::{self, stdin, stdout, BufRead, BufWriter, IsTerminal, Read, Write as _},
+    ops::Range,
+    process,
+    str::FromStr,
     thread,
     time::{Duration, Instant},
 };
 
-use colored::Colorize;
+use brainf_ck_rs::{
+    optimize,
+    parse::{self, ParseError, ParsedProgram},
+    CellSize, Eof, Interpreter, Memory, Op, OpList, OutputFormat, Position, StepOutcome, TapeError,
+    UnmatchedBracket,
+};
+use cli::Command;
+use colored::Color;
+use condition::CellCondition;
+use cycles::CostModel;
+use preview::PreviewStyle;
+use profile::Profile;
 
 enum ErrorKind {
     ParseOptionParam(&'static str, &'static str),
     MissingOptionParam(&'static str),
+    InvalidCompiled(&'static str),
+    ParseCostModel(usize),
+    ScanLimit(usize, usize),
+    InvalidProgram(ParseError),
+    /// Offset of the first byte of the output differing from the input.
+    RoundTripMismatch(usize),
+    /// Offset of the first byte of the `from-text` output differing from
+    /// the text.
+    TextMismatch(usize),
+    ParseInputByte(&'static str, String),
+    UnmatchedBracket(UnmatchedBracket),
+    /// The tape limit and the op count when it was hit.
+    MemoryLimit(usize, usize),
+    /// Index of the `<` that moved left of the first cell.
+    LeftEdge(usize),
+    EvalWithPath,
+    /// The option and the command it doesn't apply to.
+    InapplicableOption(String, &'static str),
+    /// The command and the option it needs.
+    RequiredOption(&'static str, &'static str),
+    /// Two options that can't be given together, see
+    /// `INCOMPATIBLE_OPTIONS`.
+    IncompatibleOptions(&'static str, &'static str),
+    /// Number of loops found by `analysis::infinite_loops`.
+    InfiniteLoops(usize),
+    /// What failed, e.g. "read `program.b`".
+    Io(String, io::Error),
 }
 
 impl Debug for ErrorKind {
@@ -24,328 +78,2046 @@ impl Debug for ErrorKind {
             ErrorKind::MissingOptionParam(option_name) => {
                 writeln!(f, "option `{}` requires parameter", option_name)
             }
+            ErrorKind::InvalidCompiled(reason) => {
+                writeln!(f, "invalid compiled program: {}", reason)
+            }
+            ErrorKind::ParseCostModel(line) => {
+                writeln!(f, "failed to parse cost model (line {})", line)
+            }
+            ErrorKind::ScanLimit(op_index, scanned) => {
+                writeln!(
+                    f,
+                    "bracket scan from op {} exceeded `max-scan` ({} ops scanned)",
+                    op_index, scanned
+                )
+            }
+            ErrorKind::InvalidProgram(e) => write!(f, "{}", e),
+            ErrorKind::RoundTripMismatch(offset) => {
+                writeln!(f, "output differs from the input at offset {}", offset)
+            }
+            ErrorKind::TextMismatch(offset) => {
+                writeln!(f, "output differs from the text at byte {}", offset)
+            }
+            ErrorKind::ParseInputByte(option_name, value) => {
+                writeln!(
+                    f,
+                    "invalid byte `{}` in `{}` (must be between 0 and 255)",
+                    value, option_name
+                )
+            }
+            ErrorKind::UnmatchedBracket(e) => writeln!(f, "invalid program: {}", e),
+            ErrorKind::MemoryLimit(limit, total_ops) => writeln!(
+                f,
+                "the pointer moved past `memory-limit` ({} cells) after {} operations",
+                limit, total_ops
+            ),
+            ErrorKind::LeftEdge(op_index) => writeln!(
+                f,
+                "the pointer moved left of the first cell at op {} (see `wrap-tape` and `infinite-tape`)",
+                op_index
+            ),
+            ErrorKind::EvalWithPath => {
+                writeln!(f, "`eval` can't be used together with a program path")
+            }
+            ErrorKind::InapplicableOption(option, command) => {
+                writeln!(f, "option `{}` doesn't apply to `{}`", option, command)
+            }
+            ErrorKind::RequiredOption(command, option) => {
+                writeln!(f, "`{}` requires the `{}` option", command, option)
+            }
+            ErrorKind::IncompatibleOptions(a, b) => {
+                writeln!(f, "`{}` can't be used together with `{}`", a, b)
+            }
+            ErrorKind::Io(action, e) => writeln!(f, "failed to {}: {}", action, e),
+            ErrorKind::InfiniteLoops(loops) => writeln!(
+                f,
+                "found {} potentially infinite loop(s) (see `strict`)",
+                loops
+            ),
         }
     }
 }
 
-fn main() -> Result<(), ErrorKind> {
-    const USAGE: &str = "Usage: brainf-ck-rs [program_path] <options>
+impl ErrorKind {
+    /// Exit status for the error: 3 when the program failed while running,
+    /// 4 when reading or writing a file failed and 1 otherwise (invalid
+    /// program or option). 2 is for reached limits, see `LIMIT_EXIT_CODE`.
+    fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::MemoryLimit(..) | ErrorKind::LeftEdge(_) | ErrorKind::ScanLimit(..) => 3,
+            ErrorKind::Io(..) => 4,
+            _ => 1,
+        }
+    }
+}
+
+/// Maps an I/O error to `ErrorKind::Io`, e.g. `io_error("read", path)`.
+fn io_error<'a>(action: &'a str, path: &'a str) -> impl FnOnce(io::Error) -> ErrorKind + 'a {
+    move |e| ErrorKind::Io(format!("{} `{}`", action, path), e)
+}
+
+impl From<UnmatchedBracket> for ErrorKind {
+    fn from(e: UnmatchedBracket) -> Self {
+        ErrorKind::UnmatchedBracket(e)
+    }
+}
+
+fn main() {
+    if let Err(e) = run_cli() {
+        eprint!("Error: {:?}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+/// Pairs of options that can't be given together: the JSON object of
+/// `--json` has no room for the reports of `--stats` and `--profile`, and
+/// `--low-memory` never reads the whole file to split it with `--all`.
+const INCOMPATIBLE_OPTIONS: [(&str, &str); 3] = [
+    ("json", "stats"),
+    ("json", "profile"),
+    ("all", "low-memory"),
+];
+
+fn run_cli() -> Result<(), ErrorKind> {
+    const USAGE: &str = "Usage: brainf-ck-rs [command] [program_path]... <options>
+
+Commands:
+    run                     Executes the program (default)
+    check                   Reports every error in the program and its
+                            size without executing it, like `--check`
+    fmt                     Prints the program formatted, like `--fmt`
+    compile                 Writes the parsed program to the `--output`
+                            file, like `--compile-to <file>`
+    debug                   Executes the program in the debugger, like
+                            `--debug`
+
+    `check`, `fmt` and `compile` only accept `--eval`, `--strict`,
+    `--extended` and `--no-color`, and `compile` also `--output`,
+    `--optimize` and `--low-memory`
 
 Arguments:
-    [program path]          The path of the program to execute
+    [program path]          The path of the program to execute, `-` (or
+                            `--stdin`) reads it from stdin (the program then
+                            gets no input from stdin). When several are
+                            given they are concatenated in order, so a
+                            prelude file can be prepended to a program (see
+                            `--each` to run them separately). Without any
+                            path, a program piped to stdin is read as if `-`
+                            was given, unless stdin is empty. A file named
+                            like a command is read as a program
 
 Options:
     --max-steps <steps>     Maximum number of steps before terminating,
                             useful when the program doesn't terminate
-                            on its own
+                            on its own. Exits with status 2 when the
+                            limit is reached
     --show-preview          Shows a preview of the operations performed
                             and of memory while executing
     --delay <delay>         Delay (in ms) between each step
+    --lint                  Scans the program for common mistakes and
+                            prints warnings instead of executing it
+    --compile-to <file>     Writes the parsed program, optimized at the
+                            `--optimize` level, to a file instead of
+                            executing it
+    --run-compiled <file>   Executes a program written by `--compile-to`
+                            (replaces [program path]), it isn't
+                            optimized again
+    --halt-at-op <op>       Stops the first time the given op (e.g. `,`)
+                            is about to be executed and prints the state
+    --low-memory            Reads the program file in chunks without
+                            keeping the source in memory, for very large
+                            programs (ignored with `--lint`)
+    --cycles                Reports a weighted op count where some ops
+                            (I/O, brackets) cost more than others
+    --cost-model <file>     Weights used by `--cycles`, one `<op> <weight>`
+                            pair per line (implies `--cycles`)
+    --from-text <text>      Generates a program printing the text, runs
+                            it and fails if the output differs from the
+                            bytes of the text (replaces [program path])
+    --max-scan <ops>        Fails if matching a bracket requires scanning
+                            more than this number of ops
+    --timeline <file>       Periodically samples the elapsed time and the
+                            number of ops performed and writes them to a
+                            csv file
+    --check                 Reports every error in the program (such as
+                            unmatched brackets) without executing it, or
+                            its number of ops, loops and nesting depth
+    --cell-display-width <width>
+                            Width of each cell in the memory preview
+                            (default: fits any value of the cell size)
+    --input-estimate        Estimates how many bytes of input the program
+                            reads instead of executing it
+    --warn-on-growth        Warns the first time the tape grows beyond its
+                            initial capacity and reports how many times it
+                            grew
+    --roundtrip             Checks that the output equals the input (for
+                            cat-like programs) and fails with the first
+                            differing offset otherwise
+    --pause-on-growth       With the preview, pauses execution every time
+                            the tape grows until enter is pressed
+    --cells-signed          Shows cells as signed values (-128 to 127 for
+                            8-bit cells) in the memory preview and with
+                            `--numeric-output`, storage is unchanged
+    --on-complete <cmd>     Runs a shell command once the program ends,
+                            only if it completed unless
+                            `--on-complete-always` is given. The
+                            `BF_HALT_REASON` environment variable is set
+                            to `completed`, `max-steps`, `time-limit`,
+                            `halt-at-op`, `quit` or `error`, and
+                            `BF_OUTPUT_PATH` to the `--output` file when
+                            one is given
+    --on-complete-always    Runs the `--on-complete` command however the
+                            program ended
+    --selftest              Runs a cat program on a generated 1MB input,
+                            checks that it is echoed exactly and reports
+                            the throughput
+    --preview-when-cell <condition>
+                            Only renders the preview on steps where the
+                            current cell matches the condition, written as
+                            an operator and a value (`==0`, `!=0`, `<10`,
+                            `>=100`...)
+    --complexity            Prints a complexity score computed from the
+                            program's structure instead of executing it
+    --input-hex <bytes>     Uses the given space-separated hex byte values
+                            as input instead of reading stdin
+    --input-dec <bytes>     Same as `--input-hex` with decimal values
+    --all                   Runs every program of a bundle file, where
+                            programs are separated by `=== <name>` lines,
+                            each with a fresh memory, then prints a
+                            summary (can't be used with `--low-memory`)
+    --highlight-color <color>
+                            Color of the current op and cell in the
+                            preview (default: red), any of black, red,
+                            green, yellow, blue, magenta, cyan, white and
+                            their `bright` variants (e.g. `bright blue`)
+    --binary-input          Reads all of stdin as raw bytes for `,` before
+                            executing, newlines and nulls included,
+                            instead of as the program reads it
+    --run-log <file>        Appends a JSON line describing the run (id,
+                            program, hashes of the program, input and
+                            output, op count, timing) to the file
+    --cell-size <bits>      Size of each cell, 8, 16 or 32 bits (default:
+                            8), values wrap at the matching bound. With
+                            wider cells `.` writes the UTF-8 encoding of
+                            the char
+    --dump-memory [cells]   Prints the memory once the program ended, the
+                            first given number of cells or every cell
+                            between the first and last nonzero ones
+    --input <text>          Uses the text as input instead of reading
+                            stdin, multi-byte chars are read one byte at
+                            a time (takes precedence over the other input
+                            options)
+    --inline-input          Uses everything after the first `!` of the
+                            program as its input (after `--input`,
+                            `--input-hex` and `--input-dec`)
+    --optimize [level]      Folds runs of the same `+`, `-`, `<` or `>`
+                            and the clear loops `[-]` and `[+]` into a
+                            single step (level 1, the default), and with
+                            level 2 loops moving the current cell to other
+                            cells like `[->+>++<<]`. Ops are still counted
+                            one by one (a replaced loop counts as one per
+                            cell) but `--max-steps` counts steps. `-O0`,
+                            `-O1` and `-O2` set the level too
+    --eof <mode>            What `,` writes once the input is exhausted,
+                            `zero`, `minus-one` or `neg-one` (the largest
+                            cell value) or `unchanged` (default). Also
+                            `--eof-mode <mode>`
+    --no-color              Disables colors in the preview, the current op
+                            and cell are marked with brackets instead
+                            (also disabled by setting `NO_COLOR`)
+    --numeric-output        Writes the decimal value of the cell followed
+                            by a space for each `.` instead of a char
+    --skip-zero             With `--numeric-output`, `.` writes nothing
+                            for a zero cell
+    --memory-limit <cells>  Fails when the pointer moves past the given
+                            number of cells instead of growing the tape,
+                            every cell takes 4 bytes whatever its size
+    --wrap-tape             Moving left of the first cell goes to the last
+                            one, and with `--memory-limit` moving right of
+                            the last cell goes to the first one
+    --profile               Counts how many times each op was executed
+                            and how many times loops jumped back, and
+                            prints the breakdown after execution
+    --debug                 Pauses before the first op, at every `#` and
+                            at every breakpoint to show the ops and memory
+                            and waits for a command: enter or `s` to step
+                            one op, `c` to continue, `b <op index>` to add
+                            or remove a breakpoint, `p <cell>` to print a
+                            cell, `set <cell> <value>` to change it, `q` to
+                            quit. Without it `#` prints the cells around
+                            the pointer to stderr
+    --emit-c <file>         Translates the program to C and writes it to
+                            the file instead of executing it, the tape
+                            has `--memory-limit` cells (default: 65536)
+    --json                  Prints a JSON object with the op count, the
+                            time taken, the output (decoded as UTF-8,
+                            invalid bytes become U+FFFD), why execution
+                            stopped and the final pointer instead of the
+                            summary and output, the other messages go to
+                            stderr. Can't be used with `--stats` or
+                            `--profile`
+    --repl                  Reads snippets from stdin and executes each
+                            one on the same memory, printing the pointer
+                            and current cell after it. `,` reads from
+                            `--input`, `quit` exits
+    --output <file>         Writes the output to the file as raw bytes
+                            instead of stdout
+    --time-limit <ms>       Stops execution once it ran for the given
+                            time, exits with status 2 like `--max-steps`
+    --stats                 Prints the highest cell the pointer reached,
+                            the number of nonzero cells and the size of
+                            the tape after execution
+    --memory <cells>        Number of cells the tape starts with (default:
+                            65536), it doubles when the pointer goes past
+                            the end
+    --bench <runs>          Executes the program the given number of times
+                            without printing its output and reports the
+                            time taken
+    --trace <file>          Writes a line per step to the file: the step,
+                            the op index, the op, the pointer and the cell
+                            before the op
+    --trace-from <step>     First step written by `--trace` (default: 0)
+    --trace-to <step>       Step `--trace` stops at, not written
+    -e, --eval <program>    Executes the given program text instead of a
+                            file (replaces [program path])
+    --preview-width <ops>   Number of ops shown on each side of the current
+                            one in the preview (default: 10), the number
+                            of memory rows is scaled along with it
+    --preview-every <steps> Only renders the preview every given number of
+                            steps
+    --extended              Recognizes two extra ops: `$` prints the nonzero
+                            cells and `!` prints the pointer and the
+                            current cell, both to stderr (ignored with
+                            `--low-memory`)
+    --strict                Fails on characters that aren't instructions,
+                            whitespace or comments instead of ignoring
+                            them, and instead of warning when a loop can't
+                            end because its body never changes the cell
+                            it tests (only checked without `--max-steps`
+                            or `--time-limit`)
+    --unbuffered            Flushes the output after every `.` instead of
+                            every line (the output is always flushed
+                            before `,`), slower
+    --infinite-tape         Moving left of the first cell adds cells before
+                            it, they are shown with negative addresses
+    --watch                 Executes the program again every time one of
+                            its files is modified, until interrupted. `,`
+                            only reads the input options, not stdin
+    --graph                 Draws the cells in the preview as bars as high
+                            as their value instead of numbers
+    --each                  Runs each [program path] as its own program
+                            instead of concatenating them, like `--all`.
+                            Every program gets the same input
+    --binary                `.` writes the lowest byte of the cell as is,
+                            even with wider cells, and stdout only gets the
+                            output: the summary goes to stderr and no
+                            newline is added. `,` always reads raw bytes
+    --input-file <path>     Uses the bytes of the file as input instead of
+                            reading stdin (after `--input`)
+    --interactive           When the program reads stdin as it runs, `,`
+                            gets every key as soon as it is pressed
+                            instead of once a line is entered, Ctrl-D ends
+                            the input and Ctrl-C stops the program (stdin
+                            must be a terminal, uses `stty`)
+    --fmt                   Prints the program without comments instead
+                            of executing it, every bracket on its own line
+                            and loop bodies indented
+
+Exit status:
+    0                       The program completed
+    1                       Invalid program or options
+    2                       A limit was reached (e.g. `--max-steps`)
+    3                       The program failed while running (e.g. the
+                            pointer moved left of the first cell)
+    4                       Reading or writing a file failed
 
 Examples:
     brainf-ck-rs helloworld.b --max-steps 1000
-    brainf-ck-rs e.b --max-steps 1000000 --preview --delay 50";
-
-    let (args, options) = cli::parse();
-
-    if let Some(path) = args.get(1) {
-        let program_string = fs::read_to_string(path).expect("Failed to read program file");
-        let max_steps = options
-            .get("max-steps")
-            .map(|param| {
-                param
-                    .as_ref()
-                    .ok_or(ErrorKind::MissingOptionParam("max-steps"))
-                    .and_then(|p| {
-                        p.parse::<usize>()
-                            .map_err(|_| ErrorKind::ParseOptionParam("max-steps", "int"))
-                    })
-            })
-            .transpose()?;
-        let show_preview = options.get("preview").is_some();
-        let delay = options
-            .get("delay")
-            .map(|param| {
-                param
-                    .as_ref()
-                    .ok_or(ErrorKind::MissingOptionParam("delay"))
-                    .and_then(|p| {
-                        p.parse::<u64>()
-                            .map(|dt| Duration::from_millis(dt))
-                            .map_err(|_| ErrorKind::ParseOptionParam("delay", "int"))
-                    })
-            })
-            .transpose()?;
+    brainf-ck-rs e.b --max-steps 1000000 --preview --delay 50
+    cat program.b | brainf-ck-rs prelude.b -
+    brainf-ck-rs -e '++++++[>++++++++++<-]>+++++.'";
+
+    let (command, mut args, mut options) = cli::parse();
+    // the options standing for a command are the same as giving it
+    let command = match command {
+        Command::Run if options.contains_key("check") => Command::Check,
+        Command::Run if options.contains_key("fmt") => Command::Fmt,
+        Command::Debug => {
+            options.insert("debug".to_string(), None);
+            Command::Debug
+        }
+        command => command,
+    };
+    if let Some(option) = options.keys().filter(|o| !command.accepts(o)).min() {
+        return Err(ErrorKind::InapplicableOption(
+            option.clone(),
+            command.name(),
+        ));
+    }
+    if let Some(&(a, b)) = INCOMPATIBLE_OPTIONS
+        .iter()
+        .find(|(a, b)| options.contains_key(*a) && options.contains_key(*b))
+    {
+        return Err(ErrorKind::IncompatibleOptions(a, b));
+    }
+    // the file written by `compile` is the `--output` one
+    let compile_to = match command {
+        Command::Compile => Some(
+            get_option(&options, "output")?
+                .ok_or(ErrorKind::RequiredOption("compile", "output"))?
+                .to_string(),
+        ),
+        _ => get_option(&options, "compile-to")?.map(str::to_string),
+    };
+    if options.contains_key("no-color") || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        colored::control::set_override(false);
+    }
+    if args.len() > 1 && options.contains_key("eval") {
+        return Err(ErrorKind::EvalWithPath);
+    }
+    // an empty stdin (e.g. `< /dev/null`) isn't a program, the usage is
+    // printed like without stdin
+    if args.len() == 1
+        && !options.contains_key("eval")
+        && !stdin().is_terminal()
+        && stdin().lock().fill_buf().is_ok_and(|buf| !buf.is_empty())
+    {
+        args.push("-".to_string());
+    }
+
+    if options.contains_key("selftest") {
+        return selftest(SELFTEST_INPUT_SIZE);
+    }
+
+    let config = Config::from_options(&options)?;
 
-        if delay.is_some() && !show_preview {
-            println!("Warning: setting a `delay` without the preview enabled will just slow down the computation...");
+    // neither executes the program, so they come before the options that
+    // change how it is executed
+    if matches!(command, Command::Check | Command::Fmt) {
+        if args.len() == 1 && !options.contains_key("eval") {
+            println!("{}", USAGE);
+            return Ok(());
         }
+        let ParsedProgram { ops, .. } = parse_program(&read_program(&args, &options)?, &config)
+            .map_err(ErrorKind::InvalidProgram)?;
+        if command == Command::Fmt {
+            print!("{}", codegen::format_program(&ops));
+        } else {
+            let complexity = analysis::complexity(&ops);
+            println!(
+                "no errors found ({} ops, {} loops, nesting depth: {})",
+                ops.len(),
+                complexity.loops,
+                complexity.max_depth
+            );
+        }
+        return Ok(());
+    }
+
+    if options.contains_key("repl") {
+        repl::run(&config, get_option(&options, "input")?.unwrap_or_default());
+        return Ok(());
+    }
+
+    if options.contains_key("each") && args.len() > 1 {
+        return run_each(&args[1..], &config, &options);
+    }
+
+    if options.contains_key("watch") && args.len() > 1 {
+        // stdin isn't read, every run gets the same input
+        let input = read_input(&options, true, None)?.unwrap_or_default();
+        watch::watch(&args[1..], &config, &input_bytes(&input));
+        return Ok(());
+    }
+
+    let mut summary = summary_writer(&config);
+    if config.delay.is_some() && !config.show_preview {
+        writeln!(summary, "Warning: setting a `delay` without the preview enabled will just slow down the computation...").map_err(summary_error)?;
+    }
+    if config.pause_on_growth && !config.show_preview {
+        writeln!(
+            summary,
+            "Warning: `pause-on-growth` has no effect without the preview enabled..."
+        )
+        .map_err(summary_error)?;
+    }
+
+    // text after `!` with `--inline-input`
+    let mut inline_input = None;
+
+    let op_list = if let Some(path) = get_option(&options, "run-compiled")? {
+        compiled::load(&fs::read(path).map_err(io_error("read", path))?)?
+    } else if let Some(text) = get_option(&options, "from-text")? {
+        OpList::new(&codegen::print_program(text))?
+    } else if args.len() > 1 && options.contains_key("low-memory") && !options.contains_key("lint")
+    {
+        let reader = args[1..].iter().try_fold(
+            Box::new(io::empty()) as Box<dyn Read>,
+            |reader, path| -> Result<_, ErrorKind> {
+                Ok(Box::new(reader.chain(open_source(path)?).chain(&b"\n"[..])))
+            },
+        )?;
+        OpList::from_ops(
+            OpList::read_ops(reader)
+                .map_err(|e| ErrorKind::Io("read the program".to_string(), e))?,
+        )?
+    } else if args.len() > 1 || options.contains_key("eval") {
+        let mut program_string = read_program(&args, &options)?;
+
+        if options.contains_key("all") {
+            return run_all(&program_string, &config, &options);
+        }
+
+        if options.contains_key("inline-input") {
+            if let Some(i) = program_string.find('!') {
+                inline_input = Some(program_string[i + 1..].to_string());
+                program_string.truncate(i);
+            }
+        }
+
+        let ParsedProgram { ops, positions } =
+            parse_program(&program_string, &config).map_err(ErrorKind::InvalidProgram)?;
+
+        let op_list = OpList::from_ops(ops)?;
 
-        const ALLOWED_CHARS: &[char] = &['<', '>', '+', '-', '.', ',', '[', ']'];
-        let program = program_string
-            .lines()
-            .filter(|line| !line.starts_with("//"))
-            .flat_map(|line| line.chars())
-            .filter(|c| ALLOWED_CHARS.contains(c))
-            .collect::<String>();
+        if options.contains_key("lint") {
+            let warnings = analysis::lint(&op_list.ops, &positions);
+            for warning in &warnings {
+                println!("{}", warning);
+            }
+            println!("{} warning(s)", warnings.len());
+            return Ok(());
+        }
+
+        if options.contains_key("complexity") {
+            let complexity = analysis::complexity(&op_list.ops);
+            println!(
+                "complexity: {} (nesting depth: {}, loops: {}, pointer span: {}, op variety: {}/{})",
+                complexity.score(),
+                complexity.max_depth,
+                complexity.loops,
+                complexity.pointer_span,
+                complexity.op_variety,
+                Op::COUNT
+            );
+            return Ok(());
+        }
+
+        if options.contains_key("input-estimate") {
+            let estimate = analysis::input_estimate(&op_list.ops);
+            if estimate.in_loops == 0 {
+                println!("reads exactly {} byte(s) of input", estimate.fixed);
+            } else {
+                println!(
+                    "reads at least {} byte(s) of input, possibly more ({} `,` in loops)",
+                    estimate.fixed, estimate.in_loops
+                );
+            }
+            return Ok(());
+        }
+
+        // without a limit a program stuck in a loop hangs silently
+        if config.max_steps.is_none() && config.time_limit.is_none() {
+            let warnings = analysis::infinite_loops(&op_list.ops, &positions);
+            for warning in &warnings {
+                eprintln!("{}", warning);
+            }
+            if config.strict && !warnings.is_empty() {
+                return Err(ErrorKind::InfiniteLoops(warnings.len()));
+            }
+        }
 
-        let mut op_list = OpList::new(&program);
-        let mut mem = Memory::new();
+        op_list
+    } else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+
+    if let Some(path) = &compile_to {
+        let op_list = optimize::optimize(op_list, config.optimize);
+        fs::write(path, compiled::save(&op_list)).map_err(io_error("write", path))?;
+        println!("compiled {} operations to {}", op_list.ops.len(), path);
+        return Ok(());
+    }
+
+    if let Some(path) = get_option(&options, "emit-c")? {
+        let tape_len = config
+            .memory_limit
+            .unwrap_or(Memory::DEFAULT_MEMORY_CAPACITY);
+        fs::write(
+            path,
+            codegen::c_program(op_list, config.cell_size, config.eof, tape_len),
+        )
+        .map_err(io_error("write", path))?;
+        println!("wrote C program to {}", path);
+        return Ok(());
+    }
+
+    let input = read_input(
+        &options,
+        op_list.ops.contains(&Op::In),
+        inline_input.as_deref(),
+    )?;
+
+    if let Some(runs) = parse_option::<usize>(&options, "bench", "int")? {
+        // every run gets the same input
+        let input = input.unwrap_or_else(read_stdin);
+        return bench(op_list, config, &input, runs.max(1));
+    }
 
-        let mut total_ops = 0;
+    let run_log = get_option(&options, "run-log")?;
+    let program_ops = run_log.map(|_| op_list.ops.clone());
 
-        let mut input = if program.contains(',') {
-            let mut input = String::new();
-            let _ = stdin().read_line(&mut input);
-            input.chars().rev().collect::<String>()
+    let mut stdin_input = StdinInput {
+        read: Vec::new(),
+        keys: options
+            .contains_key("interactive")
+            .then(terminal::CharInput::new),
+    };
+    let result = match &input {
+        Some(input) => run(op_list, &config, &input_bytes(input)[..]),
+        None => run(op_list, &config, &mut stdin_input),
+    };
+    // the whole input is compared and hashed, not only what the program
+    // read before it stopped
+    let needs_input = options.contains_key("roundtrip") || run_log.is_some();
+    if input.is_none() && needs_input && !stdin().is_terminal() {
+        stdin()
+            .read_to_end(&mut stdin_input.read)
+            .map_err(|e| ErrorKind::Io("read stdin".to_string(), e))?;
+    }
+    let input = input.unwrap_or_else(|| stdin_input.read.into_iter().map(char::from).collect());
+
+    if let Some(cmd) = get_option(&options, "on-complete")? {
+        let reason = match &result {
+            Ok(outcome) => outcome.halt.as_str(),
+            Err(_) => "error",
+        };
+        if matches!(
+            result,
+            Ok(Outcome {
+                halt: Halt::Completed,
+                ..
+            })
+        ) || options.contains_key("on-complete-always")
+        {
+            let (shell, flag) = if cfg!(windows) {
+                ("cmd", "/C")
+            } else {
+                ("sh", "-c")
+            };
+            let mut command = process::Command::new(shell);
+            command.args([flag, cmd]).env("BF_HALT_REASON", reason);
+            if let Some(path) = &config.output_path {
+                command.env("BF_OUTPUT_PATH", path);
+            }
+            command
+                .status()
+                .map_err(|e| ErrorKind::Io("run the `on-complete` command".to_string(), e))?;
+        }
+    }
+
+    let Outcome {
+        output,
+        bytes,
+        halt,
+        total_ops,
+        elapsed,
+        ptr,
+    } = result?;
+
+    if options.contains_key("json") {
+        writeln!(
+            stdout(),
+            "{{\"total_ops\":{},\"elapsed_ms\":{:.3},\"output\":\"{}\",\"halt\":\"{}\",\"ptr\":{}}}",
+            total_ops,
+            elapsed.as_secs_f64() * 1000.,
+            runlog::escape(&String::from_utf8_lossy(&bytes)),
+            halt.as_str(),
+            ptr
+        )
+        .map_err(summary_error)?;
+    }
+
+    if let (Some(path), Some(ops)) = (run_log, &program_ops) {
+        let program = if let Some(path) = get_option(&options, "run-compiled")? {
+            path.to_string()
+        } else if options.contains_key("from-text") {
+            "<text>".to_string()
+        } else if options.contains_key("eval") {
+            "<eval>".to_string()
         } else {
-            String::new()
+            args[1..].join(" ")
         };
+        runlog::append(
+            path,
+            &runlog::Record {
+                program: &program,
+                ops,
+                input: &input,
+                output: &output,
+                halt: halt.as_str(),
+                total_ops,
+                elapsed,
+            },
+        )
+        .map_err(io_error("write", path))?;
+    }
 
-        let mut output = String::new();
+    if options.contains_key("roundtrip") {
+        match mismatch(&bytes, &input_bytes(&input)) {
+            Some(offset) => return Err(ErrorKind::RoundTripMismatch(offset)),
+            None => writeln!(summary, "round-trip ok: output matches the input")
+                .map_err(summary_error)?,
+        }
+    }
 
-        let start = Instant::now();
-        while op_list.pos < op_list.ops.len()
-            && max_steps.map(|limit| total_ops < limit).unwrap_or(true)
-        {
-            if show_preview {
-                op_list.display();
-                mem.display();
+    if let Some(text) = get_option(&options, "from-text")? {
+        match mismatch(&bytes, text.as_bytes()) {
+            Some(offset) => return Err(ErrorKind::TextMismatch(offset)),
+            None => writeln!(summary, "round-trip ok: output matches the text")
+                .map_err(summary_error)?,
+        }
+    }
+
+    if matches!(halt, Halt::MaxSteps | Halt::TimeLimit) {
+        process::exit(LIMIT_EXIT_CODE);
+    }
+
+    Ok(())
+}
+
+/// Exit code when execution was stopped by `--max-steps` or `--time-limit`,
+/// errors exit with 1.
+const LIMIT_EXIT_CODE: i32 = 2;
+
+/// Options controlling how a program is executed.
+#[derive(Default)]
+struct Config {
+    max_steps: Option<usize>,
+    /// Stops execution once it has run for this long.
+    time_limit: Option<Duration>,
+    show_preview: bool,
+    delay: Option<Duration>,
+    halt_at_op: Option<Op>,
+    cost_model: Option<CostModel>,
+    max_scan: Option<usize>,
+    timeline: Option<String>,
+    cell_size: CellSize,
+    /// Number of cells the tape starts with.
+    memory_capacity: Option<usize>,
+    /// Maximum number of cells of the tape.
+    memory_limit: Option<usize>,
+    /// Makes the tape circular instead of failing at its edges.
+    wrap_tape: bool,
+    /// Moving left of the first cell adds cells before it.
+    infinite_tape: bool,
+    eof: Eof,
+    output_format: OutputFormat,
+    /// With numeric output, `.` writes nothing for a zero cell.
+    skip_zero: bool,
+    preview_style: PreviewStyle,
+    warn_on_growth: bool,
+    pause_on_growth: bool,
+    /// Doesn't print the summary and output after execution.
+    quiet: bool,
+    /// Keeps the output in memory even when it is streamed to stdout.
+    capture_output: bool,
+    /// Only renders the preview on steps where the current cell matches.
+    preview_when: Option<CellCondition>,
+    /// The preview is only rendered every this number of steps.
+    preview_every: usize,
+    /// Prints the memory once the program ended, the first cells or the
+    /// nonzero range when no length is given.
+    dump_memory: Option<Option<usize>>,
+    /// Optimization level, see `optimize::optimize`.
+    optimize: u8,
+    /// Counts the ops executed by type and prints them after execution.
+    profile: bool,
+    /// File the output is written to instead of stdout.
+    output_path: Option<String>,
+    /// Prints how much of the tape was used after execution.
+    stats: bool,
+    /// File each step is logged to, with the range of steps logged.
+    trace: Option<(String, Range<usize>)>,
+    /// Pauses at every `#` and waits for a debugger command.
+    debug: bool,
+    /// Parses `$` and `!`, which print the tape and the pointer to stderr.
+    extended: bool,
+    /// Turns the infinite loop warnings and unknown characters into errors.
+    strict: bool,
+    /// Flushes the output after every `.`.
+    unbuffered: bool,
+    /// Leaves stdout to the output, unchanged: the summary goes to stderr.
+    binary: bool,
+}
+
+impl Config {
+    fn from_options(options: &HashMap<String, Option<String>>) -> Result<Self, ErrorKind> {
+        let signed = options.contains_key("cells-signed");
+        let cell_size =
+            parse_option::<CellSize>(options, "cell-size", "8, 16 or 32")?.unwrap_or_default();
+        Ok(Config {
+            max_steps: parse_option::<usize>(options, "max-steps", "int")?,
+            time_limit: parse_option::<u64>(options, "time-limit", "int")?
+                .map(Duration::from_millis),
+            show_preview: options.contains_key("preview"),
+            delay: parse_option::<u64>(options, "delay", "int")?.map(Duration::from_millis),
+            halt_at_op: parse_option::<Op>(options, "halt-at-op", "op char")?,
+            cost_model: if let Some(path) = get_option(options, "cost-model")? {
+                Some(CostModel::parse(
+                    &fs::read_to_string(path).map_err(io_error("read", path))?,
+                )?)
+            } else if options.contains_key("cycles") {
+                Some(CostModel::default())
+            } else {
+                None
+            },
+            max_scan: parse_option::<usize>(options, "max-scan", "int")?,
+            timeline: get_option(options, "timeline")?.map(|p| p.to_string()),
+            cell_size,
+            memory_capacity: parse_option::<usize>(options, "memory", "int")?,
+            memory_limit: parse_option::<usize>(options, "memory-limit", "int")?,
+            wrap_tape: options.contains_key("wrap-tape"),
+            infinite_tape: options.contains_key("infinite-tape"),
+            eof: parse_option::<Eof>(options, "eof", "zero, minus-one or unchanged")?
+                .unwrap_or_default(),
+            output_format: if options.contains_key("numeric-output") && signed {
+                OutputFormat::SignedNumeric
+            } else if options.contains_key("numeric-output") {
+                OutputFormat::Numeric
+            } else if options.contains_key("binary") {
+                OutputFormat::Byte
+            } else {
+                OutputFormat::Char
+            },
+            skip_zero: options.contains_key("skip-zero"),
+            preview_style: PreviewStyle {
+                cell_width: parse_option::<usize>(options, "cell-display-width", "int")?
+                    .unwrap_or(preview::default_cell_width(cell_size, signed)),
+                signed,
+                highlight: get_option(options, "highlight-color")?
+                    .map(|name| {
+                        preview::parse_color(name)
+                            .ok_or(ErrorKind::ParseOptionParam("highlight-color", "color name"))
+                    })
+                    .transpose()?
+                    .unwrap_or(Color::Red),
+                graph: options.contains_key("graph"),
+                ..PreviewStyle::default()
             }
+            .with_width(
+                parse_option::<usize>(options, "preview-width", "int")?
+                    .unwrap_or(PreviewStyle::default().op_range),
+            ),
+            warn_on_growth: options.contains_key("warn-on-growth"),
+            pause_on_growth: options.contains_key("pause-on-growth"),
+            quiet: options.contains_key("json"),
+            capture_output: ["run-log", "roundtrip", "from-text", "all", "each", "json"]
+                .iter()
+                .any(|&key| options.contains_key(key)),
+            preview_when: parse_option::<CellCondition>(options, "preview-when-cell", "condition")?,
+            preview_every: parse_option::<usize>(options, "preview-every", "int")?
+                .unwrap_or(1)
+                .max(1),
+            dump_memory: options
+                .get("dump-memory")
+                .map(|len| {
+                    len.as_deref()
+                        .map(str::parse)
+                        .transpose()
+                        .map_err(|_| ErrorKind::ParseOptionParam("dump-memory", "int"))
+                })
+                .transpose()?,
+            optimize: match (
+                options.get("optimize"),
+                ["O0", "O1", "O2"]
+                    .iter()
+                    .rposition(|level| options.contains_key(*level)),
+            ) {
+                (Some(Some(level)), _) => level
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|&level| level <= 2)
+                    .ok_or(ErrorKind::ParseOptionParam("optimize", "0, 1 or 2"))?,
+                (Some(None), _) => 1,
+                (None, Some(level)) => level as u8,
+                (None, None) => 0,
+            },
+            profile: options.contains_key("profile"),
+            output_path: get_option(options, "output")?.map(|p| p.to_string()),
+            stats: options.contains_key("stats"),
+            trace: get_option(options, "trace")?
+                .map(|path| -> Result<_, ErrorKind> {
+                    let from = parse_option::<usize>(options, "trace-from", "int")?.unwrap_or(0);
+                    let to =
+                        parse_option::<usize>(options, "trace-to", "int")?.unwrap_or(usize::MAX);
+                    Ok((path.to_string(), from..to))
+                })
+                .transpose()?,
+            debug: options.contains_key("debug"),
+            extended: options.contains_key("extended"),
+            strict: options.contains_key("strict"),
+            unbuffered: options.contains_key("unbuffered"),
+            binary: options.contains_key("binary"),
+        })
+    }
+}
 
-            let op = op_list.get();
-            match op {
-                Op::Left => mem.left(),
-                Op::Right => mem.right(),
-                Op::Incr => mem.incr(),
-                Op::Decr => mem.decr(),
-                Op::Out => {
-                    output.push(mem.read() as char);
-                    if show_preview {
-                        println!("{}", mem.read());
-                        println!("out: {}", output)
-                    }
-                }
-                Op::In => {
-                    if let Some(c) = input.pop() {
-                        mem.set(c as u8);
-                    }
-                }
-                Op::Open if mem.read() == 0 => {
-                    let mut n_brackets = 0;
-                    op_list.pos += 1;
-
-                    while op_list.get() != Op::Close || n_brackets != 0 {
-                        if op_list.get() == Op::Open {
-                            n_brackets += 1;
-                        } else if op_list.get() == Op::Close {
-                            n_brackets -= 1;
-                        }
-                        op_list.pos += 1;
-                    }
-                }
-                Op::Close if mem.read() != 0 => {
-                    let mut n_brackets = 0;
-                    op_list.pos -= 1;
-
-                    while op_list.get() != Op::Open || n_brackets != 0 {
-                        if op_list.get() == Op::Close {
-                            n_brackets += 1;
-                        } else if op_list.get() == Op::Open {
-                            n_brackets -= 1;
-                        }
-                        op_list.pos -= 1;
-                    }
+/// Why execution stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Halt {
+    /// The end of the program was reached.
+    Completed,
+    MaxSteps,
+    TimeLimit,
+    AtOp,
+    /// The debugger was told to quit.
+    Quit,
+}
+
+impl Halt {
+    fn as_str(self) -> &'static str {
+        match self {
+            Halt::Completed => "completed",
+            Halt::MaxSteps => "max-steps",
+            Halt::TimeLimit => "time-limit",
+            Halt::AtOp => "halt-at-op",
+            Halt::Quit => "quit",
+        }
+    }
+}
+
+/// What a run produced.
+struct Outcome {
+    /// Empty when the output was streamed to stdout without being captured.
+    output: String,
+    /// The bytes of `output` as they were written.
+    bytes: Vec<u8>,
+    halt: Halt,
+    total_ops: usize,
+    elapsed: Duration,
+    /// Pointer once execution stopped.
+    ptr: isize,
+}
+
+/// Parses the program and executes it with the given input, see `run`.
+fn execute(program: &str, config: &Config, input: &[u8]) -> Result<Outcome, ErrorKind> {
+    let ParsedProgram { ops, .. } =
+        parse_program(program, config).map_err(ErrorKind::InvalidProgram)?;
+    run(OpList::from_ops(ops)?, config, input)
+}
+
+/// Executes the program with the given input and returns its output and
+/// why it stopped.
+fn run(op_list: OpList, config: &Config, input: impl Read) -> Result<Outcome, ErrorKind> {
+    let &Config {
+        max_steps,
+        time_limit,
+        show_preview,
+        delay,
+        halt_at_op,
+        ref cost_model,
+        max_scan,
+        ref timeline,
+        cell_size,
+        memory_capacity,
+        memory_limit,
+        wrap_tape,
+        infinite_tape,
+        eof,
+        output_format,
+        skip_zero,
+        preview_style,
+        warn_on_growth,
+        pause_on_growth,
+        quiet,
+        capture_output,
+        preview_when,
+        preview_every,
+        dump_memory,
+        optimize,
+        profile,
+        ref output_path,
+        stats,
+        ref trace,
+        debug,
+        extended,
+        strict: _,
+        unbuffered,
+        binary,
+    } = config;
+
+    // the preview prints the output so far at each `.`, so it is buffered
+    let stream = !quiet && !show_preview;
+    let file = output_path
+        .as_ref()
+        .map(|path| fs::File::create(path).map_err(io_error("create", path)))
+        .transpose()?
+        .map(BufWriter::new);
+    let stream = stream && file.is_none();
+    let output = OutputSink {
+        stdout: stream.then(|| stdout().lock()),
+        file,
+        captured: (!stream || capture_output).then(Vec::new),
+        last: None,
+        unbuffered,
+    };
+    let op_list = optimize::optimize(op_list, optimize);
+    let mut interpreter = Interpreter::from_op_list(op_list)
+        .with_cell_size(cell_size)
+        .with_memory_limit(memory_limit.unwrap_or(usize::MAX))
+        .with_memory_capacity(memory_capacity.unwrap_or(Memory::DEFAULT_MEMORY_CAPACITY))
+        .with_eof(eof)
+        .with_output_format(output_format)
+        .with_input(input)
+        .with_output(output);
+    if wrap_tape {
+        interpreter = interpreter.with_wrapping_tape();
+    }
+    if infinite_tape {
+        interpreter = interpreter.with_infinite_tape();
+    }
+    if skip_zero {
+        interpreter = interpreter.with_zero_skipped();
+    }
+
+    // with `--optimize` a step can perform several ops
+    let mut steps = 0;
+    let mut total_ops = 0;
+    let mut cycles = 0;
+    let mut op_profile = profile.then(Profile::default);
+
+    /// Number of steps between two checks of the elapsed time.
+    const TIME_CHECK_STEPS: usize = 4096;
+    /// Minimum time between two timeline samples.
+    const TIMELINE_INTERVAL: Duration = Duration::from_millis(10);
+    let mut timeline = timeline
+        .as_ref()
+        .map(|path| -> Result<_, ErrorKind> {
+            let mut file =
+                BufWriter::new(fs::File::create(path).map_err(io_error("create", path))?);
+            writeln!(file, "elapsed_ms,total_ops").map_err(io_error("write", path))?;
+            Ok((file, path))
+        })
+        .transpose()?;
+    let mut last_sample = Duration::ZERO;
+
+    let mut trace = trace
+        .as_ref()
+        .map(|(path, range)| -> Result<_, ErrorKind> {
+            let mut file =
+                BufWriter::new(fs::File::create(path).map_err(io_error("create", path))?);
+            writeln!(file, "step op_index op ptr cell").map_err(io_error("write", path))?;
+            Ok((file, path, range.clone()))
+        })
+        .transpose()?;
+
+    let mut summary = summary_writer(config);
+
+    let mut stopped = None;
+    // the debugger starts paused and pauses before every op until a
+    // `continue` command
+    let mut stepping = debug;
+    let mut breakpoints = vec![false; interpreter.op_list.ops.len()];
+
+    let capacity = interpreter.mem.data.len();
+
+    let start = Instant::now();
+    let timed_out = |steps: usize| {
+        time_limit
+            .is_some_and(|limit| steps.is_multiple_of(TIME_CHECK_STEPS) && start.elapsed() >= limit)
+    };
+
+    // with nothing to do between steps the bookkeeping of the loop below is
+    // skipped, it roughly halves the speed (profiling is cheap enough to be
+    // done in both)
+    let hooks = show_preview
+        || halt_at_op.is_some()
+        || cost_model.is_some()
+        || max_scan.is_some()
+        || timeline.is_some()
+        || delay.is_some()
+        || warn_on_growth
+        || trace.is_some()
+        || debug
+        || extended
+        || interpreter.op_list.ops.contains(&Op::Debug);
+    if !hooks {
+        let limit = max_steps.unwrap_or(usize::MAX);
+        while steps < limit {
+            if timed_out(steps) {
+                stopped = Some(Halt::TimeLimit);
+                break;
+            }
+            let info = match interpreter.step() {
+                StepOutcome::Continue(info) => info,
+                StepOutcome::Finished => break,
+                StepOutcome::Error(e) => return Err(output_error(e)),
+                StepOutcome::Tape(e) => {
+                    return Err(tape_error(e, interpreter.op_list.pos, total_ops))
                 }
-                _ => {}
+            };
+            if let Some(op_profile) = op_profile.as_mut() {
+                // a `]` that didn't jump back moved to the next op
+                let jumped = info.op == Op::Close && interpreter.op_list.pos != info.index + 1;
+                op_profile.record(info.op, info.count as usize, jumped);
             }
+            total_ops += info.count as usize;
+            steps += 1;
+        }
+    }
+
+    while hooks
+        && !interpreter.op_list.is_finished()
+        && max_steps.map(|limit| steps < limit).unwrap_or(true)
+    {
+        if timed_out(steps) {
+            stopped = Some(Halt::TimeLimit);
+            break;
+        }
 
-            op_list.pos += 1;
-            total_ops += 1;
+        let Interpreter {
+            ref mut mem,
+            ref op_list,
+            ..
+        } = interpreter;
+
+        let render = show_preview
+            && steps.is_multiple_of(preview_every)
+            && preview_when.is_none_or(|c| c.matches(mem.read()));
+        if render {
+            preview::display_ops(op_list, &preview_style, &mut summary)
+                .and_then(|_| preview::display_memory(mem, &preview_style, &mut summary))
+                .map_err(summary_error)?;
+        }
 
-            if let Some(delay) = delay {
-                thread::sleep(delay);
+        let op = op_list.get();
+        if halt_at_op == Some(op) {
+            if !render {
+                preview::display_ops(op_list, &preview_style, &mut summary)
+                    .and_then(|_| preview::display_memory(mem, &preview_style, &mut summary))
+                    .map_err(summary_error)?;
             }
-            if show_preview {
-                println!()
+            writeln!(summary, "halted at op {} (`{}`)", op_list.pos, op).map_err(summary_error)?;
+            stopped = Some(Halt::AtOp);
+            break;
+        }
+
+        if debug && (op == Op::Debug || stepping || breakpoints[op_list.pos]) {
+            if !render {
+                preview::display_ops(op_list, &preview_style, &mut summary)
+                    .and_then(|_| preview::display_memory(mem, &preview_style, &mut summary))
+                    .map_err(summary_error)?;
+            }
+            match debug_prompt(op_list.pos, mem, &mut breakpoints, &mut summary)
+                .map_err(summary_error)?
+            {
+                DebugCommand::Continue => stepping = false,
+                DebugCommand::Step => stepping = true,
+                DebugCommand::Quit => {
+                    stopped = Some(Halt::Quit);
+                    break;
+                }
             }
         }
 
-        println!(
-            "performed {} operations in {:.1}ms",
-            total_ops,
-            start.elapsed().as_secs_f32() * 1000.
-        );
-        if !output.is_empty() {
-            println!("output:\n{}", output);
+        let count = op_list.count(op_list.pos);
+        if let Some(cost_model) = cost_model {
+            cycles += cost_model.weight(op) * count as u64;
+        }
+
+        match op {
+            _ if max_scan.is_none() => {}
+            Op::Open if mem.read() == 0 => check_scan(
+                max_scan,
+                op_list.pos,
+                op_list.matching(op_list.pos) - op_list.pos,
+            )?,
+            Op::Close if mem.read() != 0 => check_scan(
+                max_scan,
+                op_list.pos,
+                op_list.pos - op_list.matching(op_list.pos),
+            )?,
+            _ => {}
+        }
+
+        if let Some((file, path, _)) = trace
+            .as_mut()
+            .filter(|(_, _, range)| range.contains(&steps))
+        {
+            // folded ops are written like in the preview, e.g. `+x8`
+            let op = match count {
+                1 => op.to_string(),
+                count => format!("{}x{}", op, count),
+            };
+            writeln!(
+                file,
+                "{} {} {} {} {}",
+                steps,
+                op_list.pos,
+                op,
+                mem.position(),
+                mem.read()
+            )
+            .map_err(io_error("write", path))?;
         }
+
+        if extended {
+            print_extended_op(op, mem);
+        }
+        if op == Op::Debug && !debug {
+            print_tape_window(op_list.pos, mem);
+        }
+
+        let jumped = op == Op::Close && mem.read() != 0;
+        let growths = mem.growths;
+        let info = match interpreter.step() {
+            StepOutcome::Continue(info) => info,
+            StepOutcome::Finished => break,
+            StepOutcome::Error(e) => return Err(output_error(e)),
+            StepOutcome::Tape(e) => return Err(tape_error(e, interpreter.op_list.pos, total_ops)),
+        };
+        let mem = &interpreter.mem;
+
+        let grew = mem.growths > growths;
+        if grew && warn_on_growth && mem.growths == 1 {
+            eprintln!(
+                "Warning: the tape grew beyond its initial capacity ({} cells) at step {}",
+                capacity, total_ops
+            );
+        }
+        if grew && pause_on_growth && show_preview {
+            preview::display_memory(mem, &preview_style, &mut summary)
+                .and_then(|_| {
+                    writeln!(
+                        summary,
+                        "paused: the tape grew to {} cells at step {}, press enter to continue",
+                        mem.data.len(),
+                        total_ops
+                    )
+                })
+                .map_err(summary_error)?;
+            let _ = stdin().read_line(&mut String::new());
+        }
+
+        if info.wrote_output() && render {
+            writeln!(
+                summary,
+                "{}\nout: {}",
+                mem.read(),
+                String::from_utf8_lossy(interpreter.output().captured())
+            )
+            .map_err(summary_error)?;
+        }
+
+        steps += 1;
+        total_ops += count as usize;
+        if let Some(op_profile) = op_profile.as_mut() {
+            op_profile.record(op, count as usize, jumped);
+        }
+
+        if let Some((file, path)) = timeline.as_mut() {
+            if steps % TIME_CHECK_STEPS == 0 {
+                let elapsed = start.elapsed();
+                if elapsed - last_sample >= TIMELINE_INTERVAL {
+                    write_sample(file, elapsed, total_ops).map_err(io_error("write", path))?;
+                    last_sample = elapsed;
+                }
+            }
+        }
+
+        // with a preview condition only the rendered frames are paced
+        if let Some(delay) = delay.filter(|_| preview_when.is_none() || render) {
+            thread::sleep(delay);
+        }
+        if render {
+            writeln!(summary).map_err(summary_error)?;
+        }
+    }
+
+    if let Some((file, path)) = timeline.as_mut() {
+        write_sample(file, start.elapsed(), total_ops)
+            .and_then(|_| file.flush())
+            .map_err(io_error("write", path))?;
+    }
+    if let Some((file, path, _)) = trace.as_mut() {
+        file.flush().map_err(io_error("write", path))?;
+    }
+
+    let output = interpreter.output_mut();
+    output.flush().map_err(output_error)?;
+    // keeps what follows on its own line
+    if !binary && output.stdout.is_some() && output.last.is_some_and(|b| b != b'\n') {
+        writeln!(summary).map_err(summary_error)?;
+    }
+
+    if !quiet {
+        if interpreter.op_list.level() > 0 {
+            writeln!(
+                summary,
+                "performed {} operations ({} steps) in {:.1}ms",
+                total_ops,
+                steps,
+                start.elapsed().as_secs_f32() * 1000.
+            )
+        } else {
+            writeln!(
+                summary,
+                "performed {} operations in {:.1}ms",
+                total_ops,
+                start.elapsed().as_secs_f32() * 1000.
+            )
+        }
+        .map_err(summary_error)?;
+        if cost_model.is_some() {
+            writeln!(summary, "cycles: {}", cycles).map_err(summary_error)?;
+        }
+        if let Some(op_profile) = &op_profile {
+            op_profile
+                .report(total_ops, &mut summary)
+                .map_err(summary_error)?;
+        }
+        if stats {
+            let mem = &interpreter.mem;
+            writeln!(
+                summary,
+                "peak pointer: {}, nonzero cells: {}, {} cells allocated ({} bytes)",
+                mem.address(mem.max_ptr),
+                mem.nonzero_cells().count(),
+                mem.data.len(),
+                mem.data.len() * size_of::<u32>()
+            )
+            .map_err(summary_error)?;
+        }
+        if warn_on_growth {
+            writeln!(
+                summary,
+                "tape grew {} time(s), {} cells allocated",
+                interpreter.mem.growths,
+                interpreter.mem.data.len()
+            )
+            .map_err(summary_error)?;
+        }
+    }
+
+    if let Some(len) = dump_memory {
+        preview::dump_memory(&interpreter.mem, &preview_style, len, &mut summary)
+            .map_err(summary_error)?;
+    }
+
+    let halt = if let Some(halt) = stopped {
+        halt
+    } else if !interpreter.op_list.is_finished() {
+        Halt::MaxSteps
     } else {
-        println!("{}", USAGE);
+        Halt::Completed
     };
+    if !quiet {
+        match (halt, max_steps, time_limit) {
+            (Halt::MaxSteps, Some(limit), _) => {
+                writeln!(summary, "terminated: reached max-steps limit of {}", limit)
+            }
+            (Halt::TimeLimit, _, Some(limit)) => writeln!(
+                summary,
+                "terminated: reached time limit of {}ms",
+                limit.as_millis()
+            ),
+            _ => Ok(()),
+        }
+        .map_err(summary_error)?;
+    }
 
-    Ok(())
+    let interpreter_ptr = interpreter.mem.position();
+    let OutputSink {
+        stdout,
+        file,
+        captured,
+        ..
+    } = interpreter.into_output();
+    let captured = captured.unwrap_or_default();
+    if stdout.is_none() && file.is_none() && !quiet && !captured.is_empty() {
+        // the bytes as they were written, not re-encoded
+        writeln!(summary, "output:")
+            .and_then(|_| summary.flush())
+            .and_then(|_| io::stdout().write_all(&captured))
+            .and_then(|_| if binary { Ok(()) } else { writeln!(summary) })
+            .map_err(summary_error)?;
+    }
+    let output = output_to_string(&captured, cell_size);
+
+    Ok(Outcome {
+        output,
+        bytes: captured,
+        halt,
+        total_ops,
+        elapsed: start.elapsed(),
+        ptr: interpreter_ptr,
+    })
 }
 
-struct Memory {
-    ptr: usize,
-    data: Vec<u8>,
+/// Where the program writes: straight to stdout or to a file as it runs,
+/// kept in memory, or both.
+struct OutputSink {
+    /// Line buffered, so the output shows up as the program goes.
+    stdout: Option<io::StdoutLock<'static>>,
+    file: Option<BufWriter<fs::File>>,
+    captured: Option<Vec<u8>>,
+    /// Last byte written.
+    last: Option<u8>,
+    /// Flushes after every write instead of every line.
+    unbuffered: bool,
 }
 
-impl Memory {
-    const DEFAULT_MEMORY_CAPACITY: usize = 65536;
+impl OutputSink {
+    fn captured(&self) -> &[u8] {
+        self.captured.as_deref().unwrap_or_default()
+    }
+}
 
-    fn new() -> Self {
-        Memory {
-            ptr: 0,
-            data: vec![0; Self::DEFAULT_MEMORY_CAPACITY],
+impl io::Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(stdout) = self.stdout.as_mut() {
+            stdout.write_all(buf)?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(buf)?;
+        }
+        if let Some(captured) = self.captured.as_mut() {
+            captured.extend_from_slice(buf);
+        }
+        if let Some(&b) = buf.last() {
+            self.last = Some(b);
+        }
+        if self.unbuffered {
+            self.flush()?;
         }
+        Ok(buf.len())
     }
 
-    fn read(&self) -> u8 {
-        self.data[self.ptr]
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(stdout) = self.stdout.as_mut() {
+            stdout.flush()?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
     }
-    fn set(&mut self, v: u8) {
-        self.data[self.ptr] = v
+}
+
+enum DebugCommand {
+    Continue,
+    Step,
+    Quit,
+}
+
+/// Asks what to do while paused before the op at `op_index`. Commands that
+/// don't resume execution (breakpoints, printing and setting cells) and
+/// unknown ones are followed by another prompt, the end of stdin continues.
+/// Cells are given by address, like in the preview. Messages go to `out`.
+fn debug_prompt(
+    op_index: usize,
+    mem: &mut Memory,
+    breakpoints: &mut [bool],
+    out: &mut dyn io::Write,
+) -> io::Result<DebugCommand> {
+    writeln!(
+        out,
+        "paused at op {}, [enter] or `s` steps, `c` continues, `b <op index>`, `p <cell>`, `set <cell> <value>`, `q` quits",
+        op_index
+    )?;
+    loop {
+        let mut line = String::new();
+        if stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            return Ok(DebugCommand::Continue);
+        }
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            [] | ["s" | "step"] => return Ok(DebugCommand::Step),
+            ["c" | "continue"] => return Ok(DebugCommand::Continue),
+            ["q" | "quit"] => return Ok(DebugCommand::Quit),
+            ["b" | "break", index] => match index.parse::<usize>() {
+                Ok(index) if index < breakpoints.len() => {
+                    breakpoints[index] = !breakpoints[index];
+                    let state = if breakpoints[index] {
+                        "added"
+                    } else {
+                        "removed"
+                    };
+                    writeln!(out, "breakpoint at op {} {}", index, state)?;
+                }
+                _ => writeln!(out, "no op at index `{}`", index)?,
+            },
+            ["p" | "print", cell] => match cell.parse().ok().and_then(|a| mem.index_of(a)) {
+                Some(i) => writeln!(out, "cell {} = {}", cell, mem.data[i])?,
+                None => writeln!(out, "no cell at address `{}`", cell)?,
+            },
+            ["set", cell, value] => {
+                match (
+                    cell.parse().ok().and_then(|a| mem.index_of(a)),
+                    value.parse::<u32>(),
+                ) {
+                    (Some(i), Ok(value)) => {
+                        mem.data[i] = value & mem.cell_size.max();
+                        writeln!(out, "cell {} = {}", cell, mem.data[i])?;
+                    }
+                    (None, _) => writeln!(out, "no cell at address `{}`", cell)?,
+                    (_, Err(_)) => writeln!(out, "invalid value `{}`", value)?,
+                }
+            }
+            _ => writeln!(out, "unknown command `{}`", line.trim())?,
+        }
     }
+}
 
-    fn left(&mut self) {
-        assert!(self.ptr != 0, "Pointer out of bounds (left)");
-        self.ptr -= 1;
+/// Error for a move off the tape by the op at `op_index`.
+fn tape_error(e: TapeError, op_index: usize, total_ops: usize) -> ErrorKind {
+    match e {
+        TapeError::Limit(limit) => ErrorKind::MemoryLimit(limit, total_ops),
+        TapeError::LeftEdge => ErrorKind::LeftEdge(op_index),
     }
-    fn right(&mut self) {
-        if self.ptr >= self.data.len() {
-            self.data
-                .extend((0..Self::DEFAULT_MEMORY_CAPACITY).map(|_| 0));
-        }
-        self.ptr += 1;
+}
+
+/// Maps a failure to read the input or write the output to
+/// `ErrorKind::Io`, but stops quietly when whatever reads the streamed output
+/// went away (e.g. `| head`).
+fn output_error(e: io::Error) -> ErrorKind {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        process::exit(0);
     }
-    fn incr(&mut self) {
-        self.set(self.read().wrapping_add(1));
+    ErrorKind::Io("read the input or write the output".to_string(), e)
+}
+
+/// Offset of the first byte differing between `a` and `b`, the length of
+/// the shorter one when one starts with the other.
+fn mismatch(a: &[u8], b: &[u8]) -> Option<usize> {
+    a.iter()
+        .zip(b)
+        .position(|(a, b)| a != b)
+        .or_else(|| (a.len() != b.len()).then(|| a.len().min(b.len())))
+}
+
+/// Where the summary, the preview and the other messages about a run go:
+/// stdout, or stderr with `--binary` so stdout only gets the output and
+/// when quiet so it only gets the JSON object of `--json`.
+fn summary_writer(config: &Config) -> Box<dyn io::Write> {
+    if config.binary || config.quiet {
+        Box::new(io::stderr())
+    } else {
+        Box::new(stdout())
     }
-    fn decr(&mut self) {
-        self.set(self.read().wrapping_sub(1));
+}
+
+/// Maps a failure to write to the `summary_writer` to `ErrorKind::Io`, but
+/// stops quietly when stdout was closed, like `output_error`.
+fn summary_error(e: io::Error) -> ErrorKind {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        process::exit(0);
     }
+    ErrorKind::Io("write the summary".to_string(), e)
+}
 
-    fn display(&self) {
-        const CHUNK_SIZE: usize = 16;
-        const CHUNKS_DISPLAYED: usize = 4;
+/// Converts bytes written by the program to a string, one char per byte
+/// with 8-bit cells, wider cells write UTF-8.
+fn output_to_string(bytes: &[u8], cell_size: CellSize) -> String {
+    if cell_size == CellSize::U8 {
+        bytes.iter().map(|&b| b as char).collect()
+    } else {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
 
-        let chunk_ptr = self.ptr - self.ptr % CHUNK_SIZE;
-        let start = chunk_ptr.saturating_sub(2 * CHUNK_SIZE);
-        let end = start.saturating_add(CHUNKS_DISPLAYED * CHUNK_SIZE);
+/// Appends a `elapsed_ms,total_ops` line to the timeline.
+fn write_sample(file: &mut impl io::Write, elapsed: Duration, total_ops: usize) -> io::Result<()> {
+    writeln!(file, "{:.3},{}", elapsed.as_secs_f64() * 1000., total_ops)
+}
 
-        println!(
-            "mem:{}",
-            self.data[start..end]
-                .chunks(CHUNK_SIZE)
-                .enumerate()
-                .map(|(chunk_i, chunk)| {
-                    let is_current_chunk = start + chunk_i * CHUNK_SIZE == chunk_ptr;
+/// Fails if a bracket-matching scan started at `op_index` went further than
+/// `max_scan` ops.
+fn check_scan(max_scan: Option<usize>, op_index: usize, scanned: usize) -> Result<(), ErrorKind> {
+    match max_scan {
+        Some(max_scan) if scanned > max_scan => Err(ErrorKind::ScanLimit(op_index, scanned)),
+        _ => Ok(()),
+    }
+}
 
-                    "\n".to_string()
-                        + &if is_current_chunk {
-                            format!("{} |", format!("{:5}", start + chunk_i * CHUNK_SIZE).red())
-                        } else {
-                            format!("{:5} |", start + chunk_i * CHUNK_SIZE)
-                        }
-                        + &chunk
-                            .iter()
-                            .enumerate()
-                            .map(|(i, v)| {
-                                if is_current_chunk && i == self.ptr % CHUNK_SIZE {
-                                    format!(" {}", format!("{:3}", v).red())
-                                } else {
-                                    format!(" {:3}", v)
-                                }
-                            })
-                            .collect::<String>()
-                })
-                .collect::<String>()
-        )
+/// Runs the program `runs` times without printing anything and reports
+/// statistics on the time taken.
+fn bench(op_list: OpList, config: Config, input: &str, runs: usize) -> Result<(), ErrorKind> {
+    let config = Config {
+        quiet: true,
+        capture_output: false,
+        ..config
+    };
+
+    let input = input_bytes(input);
+    let mut times = Vec::with_capacity(runs);
+    let mut total_ops = 0;
+    for _ in 0..runs {
+        let outcome = run(op_list.clone(), &config, &input[..])?;
+        times.push(outcome.elapsed);
+        total_ops = outcome.total_ops;
+    }
+    times.sort();
+
+    let ms = |d: Duration| d.as_secs_f64() * 1000.;
+    let mean = times.iter().sum::<Duration>() / runs as u32;
+    println!(
+        "{} run(s) of {} operations: min {:.1}ms, median {:.1}ms, mean {:.1}ms, max {:.1}ms",
+        runs,
+        total_ops,
+        ms(times[0]),
+        ms(times[runs / 2]),
+        ms(mean),
+        ms(times[runs - 1])
+    );
+    println!(
+        "{:.0} operations/s (mean)",
+        total_ops as f64 / mean.as_secs_f64().max(f64::MIN_POSITIVE)
+    );
+
+    Ok(())
+}
+
+/// Size of the input of `--selftest`.
+const SELFTEST_INPUT_SIZE: usize = 1 << 20;
+
+/// Runs a cat program on a generated input of `input_size` bytes and checks
+/// that it is echoed byte for byte, reporting the throughput.
+fn selftest(input_size: usize) -> Result<(), ErrorKind> {
+    const CAT: &str = ",[.[-],]";
+
+    // printable ascii in lines of 64 chars, never contains 0 which would
+    // stop the cat program
+    let input = (0..input_size)
+        .map(|i| {
+            if i % 65 == 64 {
+                '\n'
+            } else {
+                (b' ' + (i % 95) as u8) as char
+            }
+        })
+        .collect::<String>();
+
+    let config = Config {
+        quiet: true,
+        ..Config::default()
+    };
+
+    let start = Instant::now();
+    let Outcome { output, .. } = run(OpList::new(CAT)?, &config, &input_bytes(&input)[..])?;
+    let elapsed = start.elapsed();
+
+    if let Some(offset) = output.chars().zip(input.chars()).position(|(a, b)| a != b) {
+        return Err(ErrorKind::RoundTripMismatch(offset));
+    }
+    if output.len() != input.len() {
+        return Err(ErrorKind::RoundTripMismatch(output.len().min(input.len())));
     }
+
+    println!(
+        "selftest ok: echoed {} bytes in {:.1}ms ({:.2} MB/s)",
+        input.len(),
+        elapsed.as_secs_f32() * 1000.,
+        input.len() as f64 / 1e6 / elapsed.as_secs_f64()
+    );
+
+    Ok(())
 }
 
-struct OpList {
-    pos: usize,
-    ops: Vec<Op>,
+/// Parses a list of space-separated byte values in the given radix. Each
+/// byte becomes the char with the same code point, which `,` turns back
+/// into the byte.
+fn parse_input_bytes(
+    bytes: &str,
+    option_name: &'static str,
+    radix: u32,
+) -> Result<String, ErrorKind> {
+    bytes
+        .split_whitespace()
+        .map(|b| {
+            u8::from_str_radix(b, radix)
+                .map(char::from)
+                .map_err(|_| ErrorKind::ParseInputByte(option_name, b.to_string()))
+        })
+        .collect()
 }
 
-impl OpList {
-    fn new(operations: &str) -> Self {
-        OpList {
-            pos: 0,
-            ops: operations.chars().map(Op::from_char).collect(),
+/// Runs every program of a bundle, see `run_programs`. Programs are
+/// separated by lines starting with `===`, optionally followed by the name of
+/// the next program.
+fn run_all(
+    source: &str,
+    config: &Config,
+    options: &HashMap<String, Option<String>>,
+) -> Result<(), ErrorKind> {
+    let mut programs = vec![(None, String::new())];
+    for line in source.lines() {
+        if let Some(name) = line.strip_prefix("===") {
+            let name = name.trim();
+            programs.push(((!name.is_empty()).then(|| name.to_string()), String::new()));
+        } else {
+            let (_, program) = programs.last_mut().unwrap();
+            program.push_str(line);
+            program.push('\n');
         }
     }
+    // text before the first delimiter is only a program if it has code,
+    // `,` and `.` alone are common in prose
+    if programs.len() > 1 && !programs[0].1.contains(['+', '-', '<', '>', '[', ']']) {
+        programs.remove(0);
+    }
+
+    let programs = programs
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, program))| (name.unwrap_or_else(|| format!("#{}", i + 1)), program))
+        .collect::<Vec<_>>();
+    run_programs(&programs, config, options)
+}
+
+/// Runs each program file on its own with a fresh memory, named after its
+/// path, like the programs of a bundle with `--all`.
+fn run_each(
+    paths: &[String],
+    config: &Config,
+    options: &HashMap<String, Option<String>>,
+) -> Result<(), ErrorKind> {
+    let programs = paths
+        .iter()
+        .map(|path| Ok((path.clone(), read_source(path)?)))
+        .collect::<Result<Vec<_>, ErrorKind>>()?;
+    run_programs(&programs, config, options)
+}
+
+/// Runs the named programs one after the other with a fresh memory, then
+/// prints a summary table. All programs get the same input.
+fn run_programs(
+    programs: &[(String, String)],
+    config: &Config,
+    options: &HashMap<String, Option<String>>,
+) -> Result<(), ErrorKind> {
+    let needs_input = programs.iter().any(|(_, program)| program.contains(','));
+    let input = input_bytes(&read_input(options, needs_input, None)?.unwrap_or_else(read_stdin));
+
+    let mut summary = summary_writer(config);
+    let mut results = Vec::new();
+    for (name, program) in programs {
+        writeln!(summary, "=== {}", name).map_err(summary_error)?;
+
+        let result = match execute(program, config, &input) {
+            Ok(Outcome { bytes, halt, .. }) => (halt.as_str(), bytes.len()),
+            Err(e) => {
+                write!(summary, "Error: {:?}", e).map_err(summary_error)?;
+                match e {
+                    ErrorKind::InvalidProgram(_) => ("invalid", 0),
+                    _ => ("error", 0),
+                }
+            }
+        };
+        results.push((name.as_str(), result));
+        writeln!(summary).map_err(summary_error)?;
+    }
 
-    fn get(&self) -> Op {
-        self.ops[self.pos]
+    let name_width = results
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(7);
+    writeln!(
+        summary,
+        "{:2$}  {:10}  output",
+        "program", "result", name_width
+    )
+    .map_err(summary_error)?;
+    for (name, (result, output_len)) in &results {
+        writeln!(
+            summary,
+            "{:3$}  {:10}  {} byte(s)",
+            name, result, output_len, name_width
+        )
+        .map_err(summary_error)?;
     }
 
-    fn display(&self) {
-        const DISPLAYED_RANGE: usize = 10;
+    Ok(())
+}
+
+/// Reads the input given to the program, `None` when the program reads
+/// stdin as it runs (see `StdinInput`). Stdin is only read up front with
+/// `--binary-input` and only if the program needs input. The input holds
+/// one char per byte.
+fn read_input(
+    options: &HashMap<String, Option<String>>,
+    needs_input: bool,
+    inline_input: Option<&str>,
+) -> Result<Option<String>, ErrorKind> {
+    Ok(Some(if let Some(text) = get_option(options, "input")? {
+        text.bytes().map(char::from).collect()
+    } else if let Some(path) = get_option(options, "input-file")? {
+        fs::read(path)
+            .map_err(io_error("read", path))?
+            .into_iter()
+            .map(char::from)
+            .collect()
+    } else if let Some(bytes) = get_option(options, "input-hex")? {
+        parse_input_bytes(bytes, "input-hex", 16)?
+    } else if let Some(bytes) = get_option(options, "input-dec")? {
+        parse_input_bytes(bytes, "input-dec", 10)?
+    } else if needs_input && options.contains_key("binary-input") {
+        read_stdin()
+    } else if let Some(text) = inline_input {
+        text.bytes().map(char::from).collect()
+    } else if needs_input {
+        return Ok(None);
+    } else {
+        String::new()
+    }))
+}
 
-        let cut_start = self.pos > DISPLAYED_RANGE;
-        let start = self.pos.saturating_sub(DISPLAYED_RANGE);
+/// Reads all of stdin, one char per byte. Stdin is never translated on any
+/// platform so newlines and nulls go through untouched.
+fn read_stdin() -> String {
+    let mut bytes = Vec::new();
+    let _ = stdin().lock().read_to_end(&mut bytes);
+    bytes.into_iter().map(char::from).collect()
+}
 
-        let cut_end = self.pos + DISPLAYED_RANGE < self.ops.len();
-        let end = (self.pos + DISPLAYED_RANGE).min(self.ops.len() - 1);
+/// The bytes read by `,`, the input holds one char per byte.
+fn input_bytes(input: &str) -> Vec<u8> {
+    input.chars().map(|c| c as u8).collect()
+}
 
-        let formatted = self.ops.iter().enumerate().collect::<Vec<_>>()[start..=end]
-            .iter()
-            .fold(String::new(), |mut out, &(i, op)| {
-                let s = op.to_char().to_string();
-                let _ = write!(out, "{}", if i == self.pos { s.red() } else { s.normal() });
-                out
-            });
+/// Reads stdin only when the program executes `,`, so interactive programs
+/// can print a prompt before each read. What was read is kept for
+/// `--run-log` and `--roundtrip`, which read the rest once the run ended.
+struct StdinInput {
+    read: Vec<u8>,
+    /// Reads a key at a time instead, with `--interactive`.
+    keys: Option<terminal::CharInput>,
+}
 
-        println!("op:");
-        println!(
-            " {} {} {} ",
-            if cut_start { "…" } else { " " },
-            formatted,
-            if cut_end { "…" } else { " " }
-        );
+impl Read for StdinInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.keys.as_mut() {
+            Some(keys) => keys.read(buf)?,
+            None => stdin().read(buf)?,
+        };
+        self.read.extend_from_slice(&buf[..n]);
+        Ok(n)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Op {
-    Left,
-    Right,
-    Incr,
-    Decr,
-    Out,
-    In,
-    Open,
-    Close,
-}
-
-impl Op {
-    fn from_char(c: char) -> Self {
-        match c {
-            '<' => Op::Left,
-            '>' => Op::Right,
-            '+' => Op::Incr,
-            '-' => Op::Decr,
-            '.' => Op::Out,
-            ',' => Op::In,
-            '[' => Op::Open,
-            ']' => Op::Close,
-            c => unreachable!("string contains illegal characters ({})", c),
-        }
-    }
-
-    fn to_char(self) -> char {
-        match self {
-            Op::Left => '<',
-            Op::Right => '>',
-            Op::Incr => '+',
-            Op::Decr => '-',
-            Op::Out => '.',
-            Op::In => ',',
-            Op::Open => '[',
-            Op::Close => ']',
+/// Parses the program, with the extended ops when `--extended` is given.
+/// With `--strict` characters that aren't instructions are errors.
+fn parse_program(source: &str, config: &Config) -> Result<ParsedProgram, ParseError> {
+    let from_char = if config.extended {
+        Op::try_from_extended_char
+    } else {
+        Op::try_from_char
+    };
+    let parsed = parse::parse_with(source, from_char);
+
+    let mut diagnostics = if config.strict {
+        parse::unknown_chars(source, from_char)
+    } else {
+        Vec::new()
+    };
+    if diagnostics.is_empty() {
+        return parsed;
+    }
+    if let Err(e) = parsed {
+        diagnostics.extend(e.diagnostics);
+    }
+    diagnostics.sort_by_key(|d| (d.pos.line, d.pos.col));
+    Err(ParseError { diagnostics })
+}
+
+/// The `--eval` program, or the program paths (`args` after the binary)
+/// concatenated in order.
+fn read_program(
+    args: &[String],
+    options: &HashMap<String, Option<String>>,
+) -> Result<String, ErrorKind> {
+    if let Some(program) = get_option(options, "eval")? {
+        return Ok(program.to_string());
+    }
+    Ok(args[1..]
+        .iter()
+        .map(|path| read_source(path))
+        .collect::<Result<Vec<_>, _>>()?
+        .join("\n"))
+}
+
+/// Prints the nonzero cells for `$` and the pointer and current cell for
+/// `!` to stderr, so the output isn't affected. Other ops print nothing.
+fn print_extended_op(op: Op, mem: &Memory) {
+    match op {
+        Op::DumpTape => eprintln!(
+            "tape: {}",
+            mem.nonzero_cells()
+                .map(|(i, v)| format!("{}:{}", mem.address(i), v))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        Op::PrintPtr => eprintln!("ptr: {}, cell: {}", mem.position(), mem.read()),
+        _ => {}
+    }
+}
+
+/// Cells shown on each side of the pointer by `print_tape_window`.
+const TAPE_WINDOW: usize = 8;
+
+/// Prints the cells around the pointer to stderr for a `#` at `op_index`,
+/// the current cell is in brackets.
+fn print_tape_window(op_index: usize, mem: &Memory) {
+    let start = mem.ptr.saturating_sub(TAPE_WINDOW);
+    let end = (mem.ptr + TAPE_WINDOW).min(mem.data.len() - 1);
+    let cells = (start..=end)
+        .map(|i| {
+            if i == mem.ptr {
+                format!("[{}]", mem.data[i])
+            } else {
+                mem.data[i].to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    eprintln!(
+        "# at op {}, ptr: {}, cells {}..={}: {}",
+        op_index,
+        mem.position(),
+        mem.address(start),
+        mem.address(end),
+        cells.join(" ")
+    );
+}
+
+/// Opens a program source, `-` is stdin.
+fn open_source(path: &str) -> Result<Box<dyn Read>, ErrorKind> {
+    Ok(if path == "-" {
+        Box::new(stdin())
+    } else {
+        Box::new(fs::File::open(path).map_err(io_error("read", path))?)
+    })
+}
+
+/// Reads a whole program source, see `open_source`.
+fn read_source(path: &str) -> Result<String, ErrorKind> {
+    let mut source = String::new();
+    open_source(path)?
+        .read_to_string(&mut source)
+        .map_err(io_error("read", path))?;
+    Ok(source)
+}
+
+/// Returns the parameter of an option, `None` if the option wasn't given.
+fn get_option<'a>(
+    options: &'a HashMap<String, Option<String>>,
+    name: &'static str,
+) -> Result<Option<&'a str>, ErrorKind> {
+    options
+        .get(name)
+        .map(|param| param.as_deref().ok_or(ErrorKind::MissingOptionParam(name)))
+        .transpose()
+}
+
+/// Parses the parameter of an option, `None` if the option wasn't given.
+fn parse_option<T: FromStr>(
+    options: &HashMap<String, Option<String>>,
+    name: &'static str,
+    param_type: &'static str,
+) -> Result<Option<T>, ErrorKind> {
+    get_option(options, name)?
+        .map(|p| {
+            p.parse::<T>()
+                .map_err(|_| ErrorKind::ParseOptionParam(name, param_type))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Config of the given options (`name` or `name=value`), quiet so the
+    /// output is only captured.
+    fn config(options: &[&str]) -> Config {
+        let options = options
+            .iter()
+            .map(|option| match option.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (option.to_string(), None),
+            })
+            .collect();
+        Config {
+            quiet: true,
+            capture_output: true,
+            ..Config::from_options(&options).unwrap()
+        }
+    }
+
+    #[test]
+    fn both_execution_loops_agree() {
+        let hello = include_str!("../examples/helloworld.b");
+        for level in ["optimize=0", "optimize=1", "optimize=2"] {
+            let fast = execute(hello, &config(&[level]), b"").unwrap();
+            // `--extended` goes through the loop with hooks between steps
+            let hooked = execute(hello, &config(&[level, "extended"]), b"").unwrap();
+            assert!(fast.output.starts_with("Hello World!"), "{}", level);
+            assert_eq!(hooked.output, fast.output, "{}", level);
+            assert_eq!(hooked.total_ops, fast.total_ops, "{}", level);
+        }
+    }
+
+    #[test]
+    fn selftest_passes() {
+        selftest(4096).unwrap();
+    }
+
+    #[test]
+    fn numeric_output_of_a_counting_program() {
+        // prints 1 to 5
+        let counting = "+++++[>+.<-]";
+        let outcome = execute(counting, &config(&["numeric-output"]), b"").unwrap();
+        assert_eq!(outcome.output, "1 2 3 4 5 ");
+
+        let outcome = execute("-.", &config(&["numeric-output", "cells-signed"]), b"").unwrap();
+        assert_eq!(outcome.output, "-1 ");
+
+        let outcome = execute("+.-.", &config(&["numeric-output", "skip-zero"]), b"").unwrap();
+        assert_eq!(outcome.output, "1 ");
+    }
+
+    #[test]
+    fn debug_op_is_ignored_without_debug() {
+        let hello = include_str!("../examples/helloworld.b");
+        let with_breakpoints = hello.replace('[', "#[").replace('.', ".#");
+        let plain = execute(hello, &config(&[]), b"").unwrap();
+        let outcome = execute(&with_breakpoints, &config(&[]), b"").unwrap();
+        assert_eq!(outcome.output, plain.output);
+        assert_eq!(outcome.halt, plain.halt);
+    }
+
+    #[test]
+    fn byte_255_is_written_as_one_byte() {
+        let outcome = execute("-.", &config(&[]), b"").unwrap();
+        assert_eq!(outcome.bytes, [0xff]);
+    }
+
+    #[test]
+    fn execute_small_programs() {
+        let hello = include_str!("../examples/helloworld.b");
+        let cases: [(&str, &[u8], &[u8]); 3] = [
+            (hello, b"", b"Hello World!\n"),
+            (",[.[-],]", b"cat\n", b"cat\n"),
+            // doubles the input cell into the next one
+            (",[->++<]>.", b"!", b"B"),
+        ];
+        for (program, input, expected) in cases {
+            let outcome = execute(program, &config(&[]), input).unwrap();
+            assert_eq!(outcome.bytes, expected, "{}", program);
+            assert_eq!(outcome.halt, Halt::Completed);
         }
     }
 }
@@ -0,0 +1,86 @@
+use crate::{Op, OpList};
+
+/// Applies the optimizations of the given level:
+/// - 0: none, every op is a step
+/// - 1: runs of `+`, `-`, `<` and `>` and clear loops (see `OpList::folded`)
+/// - 2: also multiplication loops (see `mul_loops`)
+///
+/// A list that was already optimized at that level or above is left as is.
+pub fn optimize(op_list: OpList, level: u8) -> OpList {
+    match (op_list.level(), level) {
+        (current, level) if level <= current => op_list,
+        (_, 1) => op_list.folded(),
+        (0, _) => mul_loops(op_list.folded()),
+        _ => mul_loops(op_list),
+    }
+}
+
+/// Replaces the loops that move the current cell's value to other cells,
+/// such as `[->+>++<<]`, with one `MulAdd` per target cell followed by a
+/// `Clear`. Such a loop only has `+`, `-`, `<` and `>`, comes back to the
+/// cell it tests and decrements it by exactly one per iteration, so each
+/// target gets the cell's value times its increment. Expects a folded list.
+pub fn mul_loops(op_list: OpList) -> OpList {
+    let mut ops = Vec::new();
+    let mut counts = Vec::new();
+    let mut targets = Vec::new();
+
+    let mut i = 0;
+    while i < op_list.ops.len() {
+        if op_list.ops[i] == Op::Open {
+            let end = op_list.matching(i);
+            if let Some(loop_targets) = mul_loop_targets(&op_list, i + 1..end) {
+                for target in loop_targets {
+                    ops.push(Op::MulAdd);
+                    counts.push(1);
+                    targets.push(target);
+                }
+                ops.push(Op::Clear);
+                counts.push(1);
+                i = end + 1;
+                continue;
+            }
+        }
+        ops.push(op_list.ops[i]);
+        counts.push(op_list.counts[i]);
+        i += 1;
+    }
+
+    OpList::from_parts(ops, counts, targets, 2)
+        .unwrap_or_else(|_| unreachable!("optimizing unbalanced the brackets"))
+}
+
+/// Offset and factor of every cell a loop body adds to, `None` if the body
+/// isn't a multiplication loop.
+fn mul_loop_targets(op_list: &OpList, body: std::ops::Range<usize>) -> Option<Vec<(isize, u32)>> {
+    let mut offset = 0isize;
+    // (offset, total change), in order of appearance
+    let mut changes = Vec::<(isize, u32)>::new();
+    for i in body {
+        let n = op_list.count(i);
+        let delta = match op_list.ops[i] {
+            Op::Left => {
+                offset -= n as isize;
+                continue;
+            }
+            Op::Right => {
+                offset += n as isize;
+                continue;
+            }
+            Op::Incr => n,
+            Op::Decr => n.wrapping_neg(),
+            _ => return None,
+        };
+        match changes.iter_mut().find(|(o, _)| *o == offset) {
+            Some((_, change)) => *change = change.wrapping_add(delta),
+            None => changes.push((offset, delta)),
+        }
+    }
+
+    let controlling = changes.iter().position(|&(o, _)| o == 0)?;
+    if offset != 0 || changes[controlling].1 != 1u32.wrapping_neg() {
+        return None;
+    }
+    changes.remove(controlling);
+    Some(changes)
+}
@@ -0,0 +1,184 @@
+use std::fmt::Display;
+
+use crate::{Op, Position};
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub pos: Position,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error at {}: {}", self.pos, self.message)
+    }
+}
+
+/// Every problem found in a program.
+#[derive(Debug)]
+pub struct ParseError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "invalid program")?;
+        self.diagnostics
+            .iter()
+            .try_for_each(|d| writeln!(f, "{}", d))
+    }
+}
+
+#[derive(Debug)]
+pub struct ParsedProgram {
+    pub ops: Vec<Op>,
+    /// Position of each op in the source.
+    pub positions: Vec<Position>,
+}
+
+/// Removes comments: everything from `;` or `//` to the end of the line.
+/// Lines are kept, so positions in the result match the source.
+pub fn strip_comments(source: &str) -> String {
+    source
+        .lines()
+        .map(|line| {
+            let end = [line.find(';'), line.find("//")]
+                .into_iter()
+                .flatten()
+                .min()
+                .unwrap_or(line.len());
+            &line[..end]
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reports every character that isn't an instruction, whitespace or part of
+/// a comment, those are silently dropped by `parse`.
+pub fn unknown_chars(source: &str, from_char: fn(char) -> Option<Op>) -> Vec<Diagnostic> {
+    strip_comments(source)
+        .lines()
+        .enumerate()
+        .flat_map(|(line_i, line)| {
+            line.chars()
+                .enumerate()
+                .filter(|&(_, c)| !c.is_whitespace() && from_char(c).is_none())
+                .map(move |(col_i, c)| Diagnostic {
+                    pos: Position {
+                        line: line_i + 1,
+                        col: col_i + 1,
+                    },
+                    message: format!("unknown character `{}`", c),
+                })
+        })
+        .collect()
+}
+
+/// Parses the source, dropping comments (see `strip_comments`) and any
+/// character that isn't an instruction. Every problem found is reported at
+/// once instead of stopping at the first one.
+pub fn parse(source: &str) -> Result<ParsedProgram, ParseError> {
+    parse_with(source, Op::try_from_char)
+}
+
+/// Like `parse`, with `from_char` deciding which characters are
+/// instructions (e.g. `Op::try_from_extended_char`).
+pub fn parse_with(
+    source: &str,
+    from_char: fn(char) -> Option<Op>,
+) -> Result<ParsedProgram, ParseError> {
+    let (ops, positions): (Vec<Op>, Vec<Position>) = strip_comments(source)
+        .lines()
+        .enumerate()
+        .flat_map(|(line_i, line)| {
+            line.chars().enumerate().filter_map(move |(col_i, c)| {
+                from_char(c).map(|op| {
+                    (
+                        op,
+                        Position {
+                            line: line_i + 1,
+                            col: col_i + 1,
+                        },
+                    )
+                })
+            })
+        })
+        .unzip();
+
+    let mut diagnostics = Vec::new();
+
+    let mut open_brackets = Vec::new();
+    for (&op, &pos) in ops.iter().zip(&positions) {
+        match op {
+            Op::Open => open_brackets.push(pos),
+            Op::Close if open_brackets.pop().is_none() => diagnostics.push(Diagnostic {
+                pos,
+                message: "unmatched `]`".to_string(),
+            }),
+            _ => {}
+        }
+    }
+    diagnostics.extend(open_brackets.into_iter().map(|pos| Diagnostic {
+        pos,
+        message: "unclosed `[`".to_string(),
+    }));
+    diagnostics.sort_by_key(|d| (d.pos.line, d.pos.col));
+
+    if diagnostics.is_empty() {
+        Ok(ParsedProgram { ops, positions })
+    } else {
+        Err(ParseError { diagnostics })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_error_is_reported_together() {
+        let e = parse("]+\n[[-]\n+]]").unwrap_err();
+        let errors = e
+            .diagnostics
+            .iter()
+            .map(|d| (d.pos.line, d.pos.col, d.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(errors, [(1, 1, "unmatched `]`"), (3, 3, "unmatched `]`")]);
+
+        let e = parse("[\n]]\n[").unwrap_err();
+        assert_eq!(e.diagnostics.len(), 2);
+        assert_eq!(e.to_string().lines().count(), 3);
+    }
+
+    #[test]
+    fn comments_are_stripped() {
+        assert_eq!(strip_comments("+++ // add three\n>."), "+++ \n>.");
+        assert_eq!(strip_comments("; setup\n+[-] ; clear"), "\n+[-] ");
+        assert_eq!(strip_comments("// a\n//b\n+"), "\n\n+");
+        // annotation words are dropped but not the instruction chars in them
+        let ops = parse("+ add one, then loop [ over it - ] done")
+            .unwrap()
+            .ops;
+        assert_eq!(ops, [Op::Incr, Op::In, Op::Open, Op::Decr, Op::Close]);
+        // which is what comments are for
+        let ops = parse("+ add one; then loop [ over it - ]").unwrap().ops;
+        assert_eq!(ops, [Op::Incr]);
+    }
+
+    #[test]
+    fn unknown_chars_are_reported_with_their_position() {
+        let diagnostics = unknown_chars("++(>+.\n  -) // (comment)", Op::try_from_char);
+        let errors = diagnostics
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "error at 1:3: unknown character `(`",
+                "error at 2:4: unknown character `)`"
+            ]
+        );
+        assert!(unknown_chars("$+!", Op::try_from_extended_char).is_empty());
+    }
+}
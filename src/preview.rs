@@ -0,0 +1,512 @@
+use std::{fmt::Write, io};
+
+use brainf_ck_rs::{CellSize, Memory, OpList};
+use colored::{Color, Colorize};
+
+/// Field width used for each cell by `display_memory`, fits any value of the
+/// cell size.
+pub fn default_cell_width(cell_size: CellSize, signed: bool) -> usize {
+    if signed {
+        cell_size
+            .to_signed(cell_size.max() / 2 + 1)
+            .to_string()
+            .len()
+    } else {
+        cell_size.max().to_string().len()
+    }
+}
+
+/// How the preview renders ops and memory.
+#[derive(Debug, Clone, Copy)]
+pub struct PreviewStyle {
+    /// Field width of each cell.
+    pub cell_width: usize,
+    /// Shows cells as signed values (255 is shown as -1 with 8-bit cells).
+    pub signed: bool,
+    /// Color of the current op and cell.
+    pub highlight: Color,
+    /// Number of ops shown on each side of the current one.
+    pub op_range: usize,
+    /// Number of rows of cells shown around the pointer.
+    pub chunks: usize,
+    /// Draws the cells as bars instead of numbers.
+    pub graph: bool,
+}
+
+impl PreviewStyle {
+    /// Sets how many ops are shown on each side of the current one, the
+    /// number of memory rows is scaled along with it.
+    pub fn with_width(self, op_range: usize) -> Self {
+        PreviewStyle {
+            op_range,
+            chunks: (op_range * CHUNKS_DISPLAYED / DISPLAYED_RANGE).max(1),
+            ..self
+        }
+    }
+}
+
+impl Default for PreviewStyle {
+    fn default() -> Self {
+        PreviewStyle {
+            cell_width: default_cell_width(CellSize::U8, false),
+            signed: false,
+            highlight: Color::Red,
+            op_range: DISPLAYED_RANGE,
+            chunks: CHUNKS_DISPLAYED,
+            graph: false,
+        }
+    }
+}
+
+/// Names accepted by `--highlight-color`.
+const COLORS: [(&str, Color); 16] = [
+    ("black", Color::Black),
+    ("red", Color::Red),
+    ("green", Color::Green),
+    ("yellow", Color::Yellow),
+    ("blue", Color::Blue),
+    ("magenta", Color::Magenta),
+    ("cyan", Color::Cyan),
+    ("white", Color::White),
+    ("bright black", Color::BrightBlack),
+    ("bright red", Color::BrightRed),
+    ("bright green", Color::BrightGreen),
+    ("bright yellow", Color::BrightYellow),
+    ("bright blue", Color::BrightBlue),
+    ("bright magenta", Color::BrightMagenta),
+    ("bright cyan", Color::BrightCyan),
+    ("bright white", Color::BrightWhite),
+];
+
+/// Color with the given name, case insensitive. Unlike `Color::from_str`
+/// unknown names are `None` instead of white.
+pub fn parse_color(name: &str) -> Option<Color> {
+    COLORS
+        .iter()
+        .find(|(color_name, _)| color_name.eq_ignore_ascii_case(name.trim()))
+        .map(|&(_, color)| color)
+}
+
+/// Whether the preview can highlight with colors, when it can't the current
+/// op and cell are marked with surrounding characters instead.
+pub fn should_colorize() -> bool {
+    colored::control::SHOULD_COLORIZE.should_colorize()
+}
+
+pub fn display_memory(
+    mem: &Memory,
+    style: &PreviewStyle,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let chunk_ptr = row_start(mem, mem.ptr);
+    // the rows are centered on the pointer's row, near the end of the tape
+    // they are shifted back so the window stays full, and they always
+    // include the pointer's row
+    let span = style.chunks * CHUNK_SIZE;
+    let start = row_start(
+        mem,
+        chunk_ptr
+            .saturating_sub(style.chunks / 2 * CHUNK_SIZE)
+            .min(mem.data.len().saturating_sub(span)),
+    );
+    let end = (start + span)
+        .max(chunk_ptr + CHUNK_SIZE)
+        .min(mem.data.len());
+
+    if style.graph {
+        writeln!(out, "mem:{}", format_graph(mem, style, start, end))
+    } else {
+        writeln!(out, "mem:{}", format_cells(mem, style, start, end, true))
+    }
+}
+
+/// Prints the first `len` cells from the origin, or every cell between the
+/// first and last nonzero ones, along with the pointer and the tape size.
+pub fn dump_memory(
+    mem: &Memory,
+    style: &PreviewStyle,
+    len: Option<usize>,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let (start, end) = match len {
+        Some(len) => (mem.origin, (mem.origin + len).min(mem.data.len())),
+        None => match (
+            mem.data.iter().position(|&v| v != 0),
+            mem.data.iter().rposition(|&v| v != 0),
+        ) {
+            (Some(first), Some(last)) => (row_start(mem, first), last + 1),
+            _ => (0, 0),
+        },
+    };
+
+    writeln!(
+        out,
+        "memory:{}",
+        format_cells(mem, style, start, end, false)
+    )?;
+    writeln!(
+        out,
+        "pointer at cell {}, {} cells allocated",
+        mem.position(),
+        mem.data.len()
+    )
+}
+
+const CHUNK_SIZE: usize = 16;
+const CHUNKS_DISPLAYED: usize = 4;
+const DISPLAYED_RANGE: usize = 10;
+
+/// Number of cells before the cell at `i` in its row. Rows are aligned on
+/// addresses so the origin always starts a row.
+fn row_offset(mem: &Memory, i: usize) -> usize {
+    mem.address(i).rem_euclid(CHUNK_SIZE as isize) as usize
+}
+
+/// Index in `data` of the first cell of the row containing the cell at `i`,
+/// the row at the left end of the tape can be shorter.
+fn row_start(mem: &Memory, i: usize) -> usize {
+    i.saturating_sub(row_offset(mem, i))
+}
+
+/// Formats the cells from `start` to `end` in rows of `CHUNK_SIZE`, each row
+/// starting with the address of its first cell (see `Memory::address`).
+/// `start` should be the start of a row.
+fn format_cells(
+    mem: &Memory,
+    style: &PreviewStyle,
+    start: usize,
+    end: usize,
+    highlight_current: bool,
+) -> String {
+    let &PreviewStyle {
+        cell_width,
+        signed,
+        highlight,
+        ..
+    } = style;
+
+    // without color the current cell is surrounded by brackets, so every
+    // cell gets a column on each side to keep the rows aligned
+    let colorize = should_colorize();
+
+    let chunk_ptr = row_start(mem, mem.ptr);
+    let column_width = match colorize {
+        false if highlight_current => cell_width + 3,
+        _ => cell_width + 1,
+    };
+
+    let mut chunk_starts = Vec::new();
+    let mut i = start;
+    while i < end {
+        chunk_starts.push(i);
+        i += CHUNK_SIZE - row_offset(mem, i);
+    }
+
+    chunk_starts
+        .into_iter()
+        .map(|chunk_start| {
+            // a row cut by the left end of the tape is aligned to the right
+            let missing = row_offset(mem, chunk_start);
+            let chunk_end = (chunk_start + CHUNK_SIZE - missing).min(end);
+            let chunk = &mem.data[chunk_start..chunk_end];
+            let is_current_chunk = highlight_current && chunk_start == chunk_ptr;
+            let address = mem.address(chunk_start) - missing as isize;
+
+            "\n".to_string()
+                + &if is_current_chunk {
+                    format!("{} |", format!("{:5}", address).color(highlight))
+                } else {
+                    format!("{:5} |", address)
+                }
+                + &" ".repeat(missing * column_width)
+                + &chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &v)| {
+                        let v = if signed {
+                            mem.cell_size.to_signed(v).to_string()
+                        } else {
+                            v.to_string()
+                        };
+                        let is_current = is_current_chunk && chunk_start + i == mem.ptr;
+                        match (is_current, colorize) {
+                            (true, true) => {
+                                format!(" {}", format!("{:>1$}", v, cell_width).color(highlight))
+                            }
+                            (true, false) => format!(" [{:>1$}]", v, cell_width),
+                            (false, false) if highlight_current => {
+                                format!("  {:>1$} ", v, cell_width)
+                            }
+                            (false, _) => format!(" {:>1$}", v, cell_width),
+                        }
+                    })
+                    .collect::<String>()
+        })
+        .collect()
+}
+
+/// Number of lines of the bars drawn by `format_graph`.
+const GRAPH_HEIGHT: usize = 8;
+/// Bar tops, by eighths of a line.
+const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Draws the cells from `start` to `end` as one column each, with a bar as
+/// high as the value is close to the largest value of the cell size. The
+/// axis below starts with the address of the first cell and marks the
+/// current one with `^`, so the graph reads without colors.
+fn format_graph(mem: &Memory, style: &PreviewStyle, start: usize, end: usize) -> String {
+    let max = mem.cell_size.max() as u64;
+    // in eighths of a line, nonzero values always get a visible bar
+    let heights = mem.data[start..end]
+        .iter()
+        .map(|&v| (v as u64 * (GRAPH_HEIGHT * 8) as u64).div_ceil(max) as usize)
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    for line in (0..GRAPH_HEIGHT).rev() {
+        out.push_str("\n      |");
+        for (i, &height) in heights.iter().enumerate() {
+            let block = BLOCKS[height.saturating_sub(line * 8).min(8)];
+            if start + i == mem.ptr && should_colorize() {
+                let _ = write!(out, "{}", block.to_string().color(style.highlight));
+            } else {
+                out.push(block);
+            }
+        }
+    }
+
+    let axis = (start..end)
+        .map(|i| if i == mem.ptr { '^' } else { '-' })
+        .collect::<String>();
+    let _ = write!(out, "\n{:5} +{}", mem.address(start), axis);
+    out
+}
+
+pub fn display_ops(
+    op_list: &OpList,
+    style: &PreviewStyle,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let range = style.op_range;
+
+    let cut_start = op_list.pos > range;
+    let start = op_list.pos.saturating_sub(range);
+
+    let cut_end = op_list.pos + range < op_list.ops.len();
+    let end = (op_list.pos + range).min(op_list.ops.len() - 1);
+
+    let formatted = op_list.ops.iter().enumerate().collect::<Vec<_>>()[start..=end]
+        .iter()
+        .fold(String::new(), |mut out, &(i, op)| {
+            let s = match op_list.count(i) {
+                1 => op.to_string(),
+                count => format!("{}x{}", op, count),
+            };
+            let _ = if i != op_list.pos {
+                write!(out, "{}", s)
+            } else if should_colorize() {
+                write!(out, "{}", s.color(style.highlight))
+            } else {
+                write!(out, "({})", s)
+            };
+            out
+        });
+
+    writeln!(out, "op: (depth: {})", op_list.depth())?;
+    writeln!(
+        out,
+        " {} {} {} ",
+        if cut_start { "…" } else { " " },
+        formatted,
+        if cut_end { "…" } else { " " }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use brainf_ck_rs::{Interpreter, Op};
+
+    #[test]
+    fn only_known_colors_parse() {
+        assert_eq!(parse_color("blue"), Some(Color::Blue));
+        assert_eq!(parse_color("Bright Blue"), Some(Color::BrightBlue));
+        assert_eq!(parse_color("purplish"), None);
+        assert_eq!(parse_color(""), None);
+    }
+
+    /// The memory preview without colors, so it can be compared as text.
+    fn memory_preview(mem: &Memory, style: &PreviewStyle) -> String {
+        colored::control::set_override(false);
+        let mut out = Vec::new();
+        display_memory(mem, style, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn wide_values_stay_aligned() {
+        let mut mem = Memory::with_cell_size(CellSize::U16);
+        mem.data[1] = 65535;
+        mem.data[17] = 7;
+        let style = PreviewStyle {
+            cell_width: default_cell_width(CellSize::U16, false),
+            ..PreviewStyle::default()
+        };
+        let preview = memory_preview(&mem, &style);
+        let rows = preview.lines().skip(1).collect::<Vec<_>>();
+        assert_eq!(rows.len(), CHUNKS_DISPLAYED);
+        assert!(rows.iter().all(|row| row.len() == rows[0].len()));
+        assert!(rows[0].contains(" 65535 "));
+        // the values line up in columns
+        assert_eq!(
+            rows[0].find("65535").unwrap() + 4,
+            rows[1].find('7').unwrap()
+        );
+    }
+
+    #[test]
+    fn current_op_and_cell_are_marked_without_colors() {
+        let mut mem = Memory::new();
+        mem.ptr = 2;
+        mem.data[2] = 42;
+        let preview = memory_preview(&mem, &PreviewStyle::default());
+        assert!(preview.contains("[ 42]"));
+
+        let mut op_list = OpList::new("+>+<").unwrap();
+        op_list.pos = 1;
+        let mut out = Vec::new();
+        display_ops(&op_list, &PreviewStyle::default(), &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("+(>)+<"));
+    }
+
+    #[test]
+    fn signed_cells_show_255_as_minus_one() {
+        let mut mem = Memory::new();
+        mem.data[1] = 255;
+        let style = PreviewStyle {
+            signed: true,
+            cell_width: default_cell_width(CellSize::U8, true),
+            ..PreviewStyle::default()
+        };
+        let preview = memory_preview(&mem, &style);
+        assert!(preview.contains("   -1 "));
+        assert!(!preview.contains("255"));
+        assert_eq!(CellSize::U8.to_signed(255), -1);
+    }
+
+    #[test]
+    fn op_display_respects_the_range() {
+        colored::control::set_override(false);
+        let mut op_list = OpList::new("+-<>.,+-<>.,").unwrap();
+        op_list.pos = 5;
+        let style = PreviewStyle::default().with_width(2);
+        let mut out = Vec::new();
+        display_ops(&op_list, &style, &mut out).unwrap();
+        let ops = String::from_utf8(out).unwrap();
+        assert_eq!(ops.lines().nth(1), Some(" … >.(,)+- … "));
+
+        // the whole list fits in the default range
+        let mut out = Vec::new();
+        display_ops(&op_list, &PreviewStyle::default(), &mut out).unwrap();
+        let ops = String::from_utf8(out).unwrap();
+        assert_eq!(ops.lines().nth(1), Some("   +-<>.(,)+-<>.,   "));
+    }
+
+    #[test]
+    fn cells_left_of_the_origin_have_negative_addresses() {
+        let mut mem = Memory::new();
+        mem.infinite = true;
+        mem.left().unwrap();
+        mem.incr();
+        assert_eq!(mem.position(), -1);
+
+        let mut out = Vec::new();
+        dump_memory(&mem, &PreviewStyle::default(), None, &mut out).unwrap();
+        let dump = String::from_utf8(out).unwrap();
+        let row = dump.lines().nth(1).unwrap();
+        assert!(row.trim_start().starts_with("-16 |"));
+        assert!(row.ends_with("   0   1"));
+        assert!(dump.contains("pointer at cell -1"));
+    }
+
+    #[test]
+    fn depth_counts_the_entered_loops() {
+        let mut interpreter = Interpreter::new("+[>+[-]<-]").unwrap();
+        // up to the `-` of the inner loop
+        interpreter.steps().take(5).for_each(drop);
+        assert_eq!(interpreter.op_list.get(), Op::Decr);
+
+        let mut out = Vec::new();
+        display_ops(&interpreter.op_list, &PreviewStyle::default(), &mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("op: (depth: 2)\n"));
+    }
+
+    #[test]
+    fn window_follows_the_pointer_within_the_tape() {
+        let rows_around = |mem: &Memory| {
+            memory_preview(mem, &PreviewStyle::default())
+                .lines()
+                .skip(1)
+                .map(|row| row.split('|').next().unwrap().trim().parse().unwrap())
+                .collect::<Vec<isize>>()
+        };
+
+        let mut mem = Memory::new();
+        assert_eq!(rows_around(&mem), [0, 16, 32, 48]);
+        mem.ptr = 1000;
+        assert_eq!(rows_around(&mem), [960, 976, 992, 1008]);
+
+        // near the end of the tape the window is shifted back to stay full
+        let mut mem = Memory::new().with_capacity(64);
+        mem.ptr = 60;
+        assert_eq!(rows_around(&mem), [0, 16, 32, 48]);
+        let mut mem = Memory::new().with_capacity(40);
+        mem.ptr = 39;
+        mem.data[39] = 7;
+        let preview = memory_preview(&mem, &PreviewStyle::default());
+        assert_eq!(rows_around(&mem), [0, 16, 32]);
+        assert!(preview.trim_end().ends_with("[  7]"));
+    }
+
+    #[test]
+    fn graph_has_a_column_per_cell_of_the_window() {
+        let style = PreviewStyle {
+            graph: true,
+            ..PreviewStyle::default()
+        };
+        let columns = |mem: &Memory| {
+            let graph = memory_preview(mem, &style);
+            let lines = graph.lines().skip(1).collect::<Vec<_>>();
+            assert_eq!(lines.len(), GRAPH_HEIGHT + 1);
+            lines
+                .iter()
+                .map(|line| line.split_once(['|', '+']).unwrap().1.chars().count())
+                .collect::<Vec<_>>()
+        };
+
+        let mut mem = Memory::new();
+        mem.data[3] = 255;
+        assert_eq!(
+            columns(&mem),
+            [CHUNKS_DISPLAYED * CHUNK_SIZE; GRAPH_HEIGHT + 1]
+        );
+        assert_eq!(
+            columns(&Memory::new().with_capacity(40)),
+            [40; GRAPH_HEIGHT + 1]
+        );
+
+        // the current cell is marked on the axis
+        mem.ptr = 3;
+        let graph = memory_preview(&mem, &style);
+        assert!(graph
+            .trim_end()
+            .ends_with(&format!("+---^{}", "-".repeat(60))));
+        assert!(graph
+            .lines()
+            .nth(1)
+            .unwrap()
+            .ends_with(&format!("|   █{}", " ".repeat(60))));
+    }
+}
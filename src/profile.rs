@@ -0,0 +1,56 @@
+use std::io::{self, Write};
+
+use crate::Op;
+
+/// How many times each op type was executed.
+#[derive(Default)]
+pub struct Profile {
+    counts: [usize; Op::COUNT],
+    /// Number of times a `]` jumped back, i.e. loop iterations after the
+    /// first one.
+    loop_jumps: usize,
+}
+
+impl Profile {
+    /// Records `count` executions of `op`, `jumped` tells whether a `]`
+    /// jumped back.
+    #[inline]
+    pub fn record(&mut self, op: Op, count: usize, jumped: bool) {
+        self.counts[op.index()] += count;
+        self.loop_jumps += jumped as usize;
+    }
+
+    /// Prints the counts of every op type that was executed, most executed
+    /// first, with their share of `total_ops`.
+    pub fn report(&self, total_ops: usize, out: &mut dyn Write) -> io::Result<()> {
+        let mut ops = Op::ALL
+            .iter()
+            .filter(|op| self.counts[op.index()] > 0)
+            .collect::<Vec<_>>();
+        ops.sort_by_key(|op| std::cmp::Reverse(self.counts[op.index()]));
+
+        writeln!(out, "profile:")?;
+        for op in ops {
+            let count = self.counts[op.index()];
+            writeln!(
+                out,
+                "    {:?} (`{}`): {} ({:.1}%)",
+                op,
+                op,
+                abbreviate(count),
+                count as f64 / total_ops.max(1) as f64 * 100.
+            )?;
+        }
+        writeln!(out, "    loop-jumps: {}", abbreviate(self.loop_jumps))
+    }
+}
+
+/// Shortens large counts, e.g. 1234567 becomes `1.2M`.
+fn abbreviate(n: usize) -> String {
+    match n {
+        0..1_000 => n.to_string(),
+        1_000..1_000_000 => format!("{:.1}k", n as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.1}M", n as f64 / 1e6),
+        _ => format!("{:.1}G", n as f64 / 1e9),
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    io::{stdin, stdout, BufRead, IsTerminal, Write},
+    mem,
+};
+
+use crate::{
+    parse_program, print_extended_op, tape_error, Config, Interpreter, Memory, OpList, OutputSink,
+    ParsedProgram, StepOutcome,
+};
+
+/// Reads snippets from stdin and executes them one after the other on the
+/// same memory, until `quit` or the end of stdin. A snippet with unclosed
+/// brackets continues on the next lines. `,` reads from `input`, which is
+/// shared by every snippet.
+pub fn run(config: &Config, input: &str) {
+    let input = input.chars().map(|c| c as u8).collect::<Vec<_>>();
+    let mut input = &input[..];
+
+    let mut mem = Memory::with_cell_size(config.cell_size)
+        .with_limit(config.memory_limit.unwrap_or(usize::MAX))
+        .with_capacity(
+            config
+                .memory_capacity
+                .unwrap_or(Memory::DEFAULT_MEMORY_CAPACITY),
+        );
+    mem.wrap = config.wrap_tape;
+    mem.infinite = config.infinite_tape;
+
+    let interactive = stdin().is_terminal();
+    let mut source = String::new();
+    let mut lines = stdin().lock().lines();
+    loop {
+        if interactive {
+            print!("{}", if source.is_empty() { "> " } else { ".. " });
+            let _ = stdout().flush();
+        }
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        if source.is_empty() && line.trim() == "quit" {
+            break;
+        }
+        source.push_str(&line);
+        source.push('\n');
+
+        let ops = match parse_program(&source, config) {
+            Ok(ParsedProgram { ops, .. }) => ops,
+            Err(e)
+                if e.diagnostics
+                    .iter()
+                    .all(|d| d.message.starts_with("unclosed")) =>
+            {
+                continue;
+            }
+            Err(e) => {
+                print!("{}", e);
+                source.clear();
+                continue;
+            }
+        };
+        source.clear();
+
+        let op_list = OpList::from_ops(ops)
+            .unwrap_or_else(|_| unreachable!("parsed program has unmatched brackets"));
+        let mut interpreter = Interpreter::from_op_list(op_list)
+            .with_eof(config.eof)
+            .with_output_format(config.output_format)
+            .with_input(&mut input)
+            .with_output(OutputSink {
+                stdout: Some(stdout().lock()),
+                file: None,
+                captured: None,
+                last: None,
+                unbuffered: config.unbuffered,
+            });
+        if config.skip_zero {
+            interpreter = interpreter.with_zero_skipped();
+        }
+        interpreter.mem = mem::take(&mut mem);
+
+        let limit = config.max_steps.unwrap_or(usize::MAX);
+        let mut steps = 0;
+        let mut total_ops = 0;
+        let error = loop {
+            if steps == limit {
+                break Some(format!("stopped after {} steps (see `max-steps`)", steps));
+            }
+            match interpreter.step() {
+                StepOutcome::Continue(info) => {
+                    if config.extended {
+                        // `$` and `!` don't change the memory
+                        print_extended_op(info.op, &interpreter.mem);
+                    }
+                    total_ops += info.count as usize
+                }
+                StepOutcome::Finished => break None,
+                StepOutcome::Error(e) => break Some(e.to_string()),
+                StepOutcome::Tape(e) => {
+                    break Some(format!(
+                        "{:?}",
+                        tape_error(e, interpreter.op_list.pos, total_ops)
+                    ))
+                }
+            }
+            steps += 1;
+        };
+
+        let output = interpreter.output_mut();
+        let _ = output.flush();
+        if output.last.is_some_and(|b| b != b'\n') {
+            println!();
+        }
+        if let Some(error) = error {
+            println!("Error: {}", error.trim_end());
+        }
+
+        mem = mem::take(&mut interpreter.mem);
+        println!("ptr: {}, cell: {}", mem.position(), mem.read());
+    }
+}
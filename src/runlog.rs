@@ -0,0 +1,79 @@
+use std::{
+    fs,
+    io::{self, Write},
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::Op;
+
+/// One line of the run log.
+pub struct Record<'a> {
+    pub program: &'a str,
+    pub ops: &'a [Op],
+    pub input: &'a str,
+    pub output: &'a str,
+    pub halt: &'a str,
+    pub total_ops: usize,
+    pub elapsed: Duration,
+}
+
+/// 64-bit FNV-1a, stable across runs and platforms so hashes from different
+/// runs can be compared.
+pub fn hash(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Hashes text the way the interpreter sees it, one byte per char.
+fn hash_chars(s: &str) -> u64 {
+    hash(s.chars().map(|c| c as u8))
+}
+
+/// Unique enough to tell runs apart: start time plus process id.
+fn run_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("{:x}-{:x}", nanos, process::id())
+}
+
+/// Escapes a string for use inside a JSON string literal.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends the record to the log as a single JSON line.
+pub fn append(path: &str, record: &Record) -> io::Result<()> {
+    let line = format!(
+        "{{\"id\":\"{}\",\"program\":\"{}\",\"program_hash\":\"{:016x}\",\"input_hash\":\"{:016x}\",\"output_hash\":\"{:016x}\",\"halt\":\"{}\",\"ops\":{},\"elapsed_ms\":{:.3}}}",
+        run_id(),
+        escape(record.program),
+        hash(record.ops.iter().flat_map(|op| op.to_string().into_bytes())),
+        hash_chars(record.input),
+        hash_chars(record.output),
+        record.halt,
+        record.total_ops,
+        record.elapsed.as_secs_f64() * 1000.
+    );
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", line)
+}
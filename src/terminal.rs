@@ -0,0 +1,59 @@
+use std::{
+    io::{self, stdin, IsTerminal, Read},
+    process::{self, Command, Stdio},
+};
+
+/// Exit status of a program interrupted with Ctrl-C, as set by shells.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+/// Bytes of Ctrl-C and Ctrl-D, which raw mode hands over as keys.
+const CTRL_C: u8 = 3;
+const CTRL_D: u8 = 4;
+
+/// Reads stdin one key at a time, as soon as it is pressed instead of once
+/// a line is entered. The terminal is only switched to raw mode while
+/// waiting for a key and restored right after, so it is never left in it
+/// however the program ends. It goes through `stty`, so it reads stdin as
+/// usual where `stty` isn't available or stdin isn't a terminal.
+pub struct CharInput {
+    /// Settings to restore, as printed by `stty -g`.
+    saved: Option<String>,
+}
+
+impl CharInput {
+    pub fn new() -> Self {
+        let saved = stdin().is_terminal().then(|| stty(&["-g"])).flatten();
+        CharInput { saved }
+    }
+}
+
+impl Read for CharInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(saved) = &self.saved else {
+            return stdin().read(buf);
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // enter still reads as a newline
+        stty(&["raw", "icrnl"]);
+        let read = stdin().read(&mut buf[..1]);
+        stty(&[saved.trim()]);
+        match (read?, buf[0]) {
+            // raw mode doesn't turn them into a signal and an end of input
+            (1, CTRL_C) => process::exit(INTERRUPTED_EXIT_CODE),
+            (1, CTRL_D) => Ok(0),
+            (n, _) => Ok(n),
+        }
+    }
+}
+
+/// Runs `stty` on the terminal of stdin, returns what it printed.
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
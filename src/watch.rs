@@ -0,0 +1,99 @@
+use std::{
+    fs,
+    io::Write,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{execute, summary_writer, Config, Outcome};
+
+/// How often the program files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Parses and executes the source on a fresh memory, reporting errors
+/// instead of returning them so the next run can still happen.
+pub fn run_once(source: &str, config: &Config, input: &[u8]) -> Option<Outcome> {
+    match execute(source, config, input) {
+        Ok(outcome) => Some(outcome),
+        Err(e) => {
+            let _ = write!(summary_writer(config), "Error: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Executes the program every time one of its files is modified, until the
+/// process is killed. A file that can't be read (e.g. while it is being
+/// saved) is read again at the next check.
+pub fn watch(paths: &[String], config: &Config, input: &[u8]) {
+    let mut last_modified = None;
+    loop {
+        let modified = paths
+            .iter()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()))
+            .collect::<Result<Vec<_>, _>>()
+            .ok();
+        if modified.is_some() && modified != last_modified {
+            let sources = paths
+                .iter()
+                .map(fs::read_to_string)
+                .collect::<Result<Vec<_>, _>>();
+            if let Ok(sources) = sources {
+                last_modified = modified;
+                let _ = writeln!(
+                    summary_writer(config),
+                    "=== {} at {}",
+                    paths.join(" "),
+                    time_of_day(SystemTime::now())
+                );
+                run_once(&sources.join("\n"), config, input);
+                let _ = writeln!(summary_writer(config));
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// `hh:mm:ss` in UTC.
+fn time_of_day(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        % 86400;
+    format!(
+        "{:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_once_can_run_again() {
+        let config = Config {
+            quiet: true,
+            capture_output: true,
+            ..Config::default()
+        };
+        let sources = ["++++++++[>++++++++<-]>+.", ",[.[-],]", "[", ">+."];
+        let outputs = sources
+            .iter()
+            .map(|source| run_once(source, &config, b"hi").map(|outcome| outcome.bytes))
+            .collect::<Vec<_>>();
+        // every run starts on a fresh memory
+        assert_eq!(
+            outputs,
+            [
+                Some(b"A".to_vec()),
+                Some(b"hi".to_vec()),
+                None,
+                Some(vec![1])
+            ]
+        );
+    }
+}
@@ -0,0 +1,592 @@
+use std::{
+    env, fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+/// Runs the binary with the given arguments and stdin.
+fn bf_with_stdin(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainf-ck-rs"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn bf(args: &[&str]) -> Output {
+    bf_with_stdin(args, b"")
+}
+
+/// Writes a file in a directory of its own for the test and returns its
+/// path.
+fn temp_file(test: &str, name: &str, contents: &[u8]) -> String {
+    let dir = env::temp_dir().join("brainf-ck-rs-tests").join(test);
+    fs::create_dir_all(&dir).unwrap();
+    let path: PathBuf = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn check_and_fmt_reject_options_executing_the_program() {
+    let path = temp_file("check_and_fmt", "ok.b", b"+[-]>++.");
+    for command in ["check", "fmt"] {
+        let output = bf(&[command, &path, "--low-memory"]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("`low-memory` doesn't apply"));
+        assert!(!stdout(&output).contains("performed"));
+    }
+}
+
+#[test]
+fn compile_requires_output() {
+    let path = temp_file("compile_requires_output", "ok.b", b"+.");
+    let output = bf(&["compile", &path]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("requires the `output` option"));
+
+    let output = bf(&["compile", &path, "--compile-to", "x.bfc"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn binary_keeps_messages_off_stdout() {
+    let output = bf(&["-e", "+++.", "--binary", "--dump-memory", "2"]);
+    assert_eq!(output.stdout, [3]);
+    assert!(stderr(&output).contains("memory:"));
+
+    let output = bf(&["-e", "+.,", "--binary", "--halt-at-op", ","]);
+    assert_eq!(output.stdout, [1]);
+    assert!(stderr(&output).contains("halted at op 2"));
+}
+
+#[test]
+fn closed_stdout_exits_quietly() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainf-ck-rs"))
+        .args(["-e", "+[.]", "--max-steps", "1000", "--json"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    // 2 for the max-steps limit when the output was written before stdout
+    // got closed, a panic would exit with 101
+    assert!(
+        matches!(output.status.code(), Some(0 | 2)),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn from_text_round_trips_non_ascii() {
+    let output = bf(&["--from-text", "héllo, wörld"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("héllo, wörld\n"));
+    assert!(stdout(&output).contains("round-trip ok"));
+
+    // wider cells write each byte of the text as a char of its own
+    let output = bf(&["--from-text", "héllo", "--cell-size", "16"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("differs from the text at byte 2"));
+}
+
+#[test]
+fn json_output_is_decoded_utf8() {
+    // `é` is written as its two UTF-8 bytes
+    let program = format!("{}.[-]{}.", "+".repeat(0xc3), "+".repeat(0xa9));
+    let output = bf(&["-e", &program, "--json"]);
+    let json = stdout(&output);
+    assert_eq!(json.lines().count(), 1);
+    let json = json.trim_end();
+    assert!(json.starts_with('{') && json.ends_with('}'));
+
+    let fields = json[1..json.len() - 1]
+        .split(',')
+        .map(|field| field.split_once(':').unwrap())
+        .collect::<Vec<_>>();
+    let keys = fields.iter().map(|(key, _)| *key).collect::<Vec<_>>();
+    assert_eq!(
+        keys,
+        [
+            "\"total_ops\"",
+            "\"elapsed_ms\"",
+            "\"output\"",
+            "\"halt\"",
+            "\"ptr\""
+        ]
+    );
+    assert!(fields[0].1.parse::<usize>().is_ok());
+    assert!(fields[1].1.parse::<f64>().is_ok());
+    assert_eq!(fields[2].1, "\"é\"");
+    assert_eq!(fields[3].1, "\"completed\"");
+    assert_eq!(fields[4].1, "0");
+}
+
+#[test]
+fn roundtrip_compares_the_whole_stdin() {
+    let output = bf_with_stdin(&["-e", ",.", "--roundtrip"], b"abc");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("differs from the input at offset 1"));
+
+    let output = bf_with_stdin(&["-e", ",[.,]", "--eof", "zero", "--roundtrip"], b"abc");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(stdout(&output).contains("round-trip ok"));
+}
+
+// the command runs in `sh`
+#[cfg(unix)]
+#[test]
+fn on_complete_gets_the_output_path() {
+    let path = temp_file("on_complete", "out.bin", b"");
+    let output = bf(&[
+        "-e",
+        "+.",
+        "--output",
+        &path,
+        "--on-complete",
+        "echo \"$BF_HALT_REASON $BF_OUTPUT_PATH\"",
+    ]);
+    assert!(stdout(&output).contains(&format!("completed {}", path)));
+}
+
+#[test]
+fn empty_stdin_prints_the_usage() {
+    let output = bf(&[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("Usage:"));
+}
+
+#[test]
+fn program_is_read_from_stdin_without_a_path() {
+    let output = bf_with_stdin(&["--numeric-output"], b"+++.");
+    assert!(stdout(&output).starts_with("3 \n"));
+}
+
+#[test]
+fn file_named_like_a_command_is_a_program() {
+    let path = temp_file("file_named_like_a_command", "run", b"++.");
+    let dir = PathBuf::from(&path).parent().unwrap().to_path_buf();
+    let output = Command::new(env!("CARGO_BIN_EXE_brainf-ck-rs"))
+        .args(["run", "--numeric-output"])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(stdout(&output).starts_with("2 \n"), "{}", stderr(&output));
+}
+
+#[test]
+fn roundtrip_reports_the_first_differing_offset() {
+    let output = bf(&[
+        "-e",
+        ",[.,]",
+        "--input",
+        "hello",
+        "--eof",
+        "zero",
+        "--roundtrip",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("round-trip ok"));
+
+    let output = bf(&["-e", ",.,+.", "--input", "ab", "--roundtrip"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("differs from the input at offset 1"));
+}
+
+#[test]
+fn roundtrip_compares_bytes() {
+    // wider cells write `é` as its two UTF-8 bytes, like the input
+    let e_acute = "++++++++++++++++[>++++++++++++++<-]>+++++++++.";
+    let output = bf(&[
+        "-e",
+        e_acute,
+        "--input",
+        "é",
+        "--cell-size",
+        "16",
+        "--roundtrip",
+    ]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(stdout(&output).contains("round-trip ok"));
+
+    // `é` is two bytes, the second one is changed
+    let output = bf_with_stdin(&["-e", ",.,+.", "--roundtrip"], "é".as_bytes());
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("differs from the input at offset 1"));
+}
+
+#[test]
+fn hex_input_is_echoed_byte_for_byte() {
+    let cat = ",[.[-],]";
+    let output = bf(&["-e", cat, "--input-hex", "48 0a c8 ff 41", "--binary"]);
+    assert_eq!(output.stdout, [0x48, 0x0a, 0xc8, 0xff, 0x41]);
+
+    let output = bf(&["-e", cat, "--input-dec", "72 10 200", "--binary"]);
+    assert_eq!(output.stdout, [72, 10, 200]);
+
+    let output = bf(&["-e", cat, "--input-hex", "48 100"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("invalid byte `100` in `input-hex`"));
+}
+
+#[test]
+fn all_runs_every_program_of_a_bundle() {
+    let bundle = "=== hi\n++++++++[>++++++++<-]>+.\n=== broken\n[\n===\n+[]\n";
+    let path = temp_file("all", "bundle.b", bundle.as_bytes());
+    let output = bf(&[&path, "--all", "--max-steps", "1000"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let stdout = stdout(&output);
+    assert!(stdout.starts_with("=== hi\nA\n"));
+    assert!(stdout.contains("=== #3\n"));
+    let summary = stdout
+        .lines()
+        .skip_while(|line| !line.starts_with("program"))
+        .map(|line| line.split_whitespace().take(2).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            ["program", "result"],
+            ["hi", "completed"],
+            ["broken", "invalid"],
+            ["#3", "max-steps"]
+        ]
+    );
+}
+
+#[test]
+fn all_skips_leading_prose_and_rejects_low_memory() {
+    let bundle = "Runs one program, then stops.\n=== hi\n++++++++[>++++++++<-]>+.\n";
+    let path = temp_file("all_prose", "bundle.b", bundle.as_bytes());
+    let output = bf(&[&path, "--all"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("=== hi\nA\n"));
+    assert!(!stdout(&output).contains("=== #1"));
+
+    let output = bf(&[&path, "--all", "--low-memory"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("`all` can't be used together with `low-memory`"));
+}
+
+#[test]
+fn binary_input_keeps_newlines_and_nulls() {
+    // stops at the end of input only, which reads as 255
+    let cat = ",+[-.,+]";
+    let bytes = b"a\r\n\0b\n\0";
+    let output = bf_with_stdin(
+        &["-e", cat, "--eof", "neg-one", "--binary-input", "--binary"],
+        bytes,
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(output.stdout, bytes);
+}
+
+#[test]
+fn unmatched_brackets_report_their_position() {
+    let output = bf(&["-e", "+-]+"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("error at 1:3: unmatched `]`"));
+
+    let output = bf(&["-e", "+\n[+[-]"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("error at 2:1: unclosed `[`"));
+    assert!(stdout(&output).is_empty());
+}
+
+#[test]
+fn input_is_fed_byte_by_byte() {
+    let cat = ",[.[-],]";
+    let output = bf(&["-e", cat, "--input", "héllo", "--binary"]);
+    assert_eq!(output.stdout, "héllo".as_bytes());
+
+    let path = temp_file("input", "cat.b", format!("{}!hi", cat).as_bytes());
+    let output = bf(&[&path, "--inline-input", "--binary"]);
+    assert_eq!(output.stdout, b"hi");
+    // `--input` wins over the inline input
+    let output = bf(&[&path, "--inline-input", "--input", "yo", "--binary"]);
+    assert_eq!(output.stdout, b"yo");
+}
+
+#[test]
+fn memory_limit_names_the_limit() {
+    let output = bf(&["-e", "+[>+]", "--memory-limit", "8"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output)
+        .contains("the pointer moved past `memory-limit` (8 cells) after 23 operations"));
+}
+
+#[test]
+fn left_edge_fails_unless_the_tape_wraps() {
+    let output = bf(&["-e", "+<"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).contains("the pointer moved left of the first cell at op 1"));
+
+    let output = bf(&[
+        "-e",
+        "+<+.",
+        "--wrap-tape",
+        "--memory-limit",
+        "4",
+        "--numeric-output",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("1 \n"));
+}
+
+#[test]
+fn repl_keeps_the_memory_between_lines() {
+    let script = b"+++\n.\n[\n-]\n>++.\nquit\n+.\n";
+    let output = bf_with_stdin(&["--repl", "--numeric-output"], script);
+    assert_eq!(output.status.code(), Some(0));
+    // the loop only runs once its bracket is closed
+    assert_eq!(
+        stdout(&output),
+        "ptr: 0, cell: 3\n3 \nptr: 0, cell: 3\nptr: 0, cell: 0\n2 \nptr: 1, cell: 2\n"
+    );
+}
+
+#[test]
+fn output_file_gets_the_raw_byte() {
+    let path = temp_file("output_file", "out.bin", b"");
+    let program = format!("{}.", "+".repeat(200));
+    let output = bf(&["-e", &program, "--output", &path]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read(&path).unwrap(), [200]);
+    assert!(stdout(&output).contains("performed 201 operations"));
+    assert!(!stdout(&output).contains("output:"));
+}
+
+#[test]
+fn byte_255_reaches_stdout_as_one_byte() {
+    let output = bf(&["-e", "-.", "--binary"]);
+    assert_eq!(output.stdout, [0xff]);
+}
+
+#[test]
+fn time_limit_stops_an_endless_loop() {
+    let output = bf(&["-e", "+[]", "--time-limit", "50"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout(&output).contains("terminated: reached time limit of 50ms"));
+}
+
+#[test]
+fn stats_report_the_peak_pointer() {
+    let output = bf(&["-e", ">>>>+<<>>>>>+<", "--stats"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("peak pointer: 7, nonzero cells: 2, 65536 cells allocated"));
+}
+
+#[test]
+fn bench_counts_the_ops_of_a_single_run() {
+    let program = "++[>+<-]>.";
+    let single = bf(&["-e", program, "--numeric-output"]);
+    assert!(stdout(&single).contains("performed 15 operations"));
+
+    let output = bf(&["-e", program, "--bench", "3", "--numeric-output"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = stdout(&output);
+    assert!(stdout.starts_with("3 run(s) of 15 operations: min "));
+    assert!(["median", "mean", "max", "operations/s"]
+        .iter()
+        .all(|stat| stdout.contains(stat)));
+    // only the stats, the output of the runs isn't shown
+    assert_eq!(stdout.lines().count(), 2);
+}
+
+#[test]
+fn unbalanced_program_fails_cleanly_in_every_mode() {
+    let path = temp_file("unbalanced", "loops.b", b"+[>+[-]<-]]");
+    for mode in [&[][..], &["-O2"], &["--extended"], &["--debug"]] {
+        let output = bf(&[&[path.as_str()][..], mode].concat());
+        assert_eq!(output.status.code(), Some(1), "{:?}", mode);
+        assert!(stderr(&output).contains("error at 1:11: unmatched `]`"));
+        assert!(!stderr(&output).contains("panicked"));
+    }
+}
+
+#[test]
+fn trace_has_a_line_per_step() {
+    let path = temp_file("trace", "trace.txt", b"");
+    let output = bf(&["-e", "++[->+<]", "--trace", &path]);
+    assert!(stdout(&output).contains("performed 13 operations"));
+    let trace = fs::read_to_string(&path).unwrap();
+    let lines = trace
+        .lines()
+        .map(|line| line.split(' ').collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1 + 13);
+    assert_eq!(lines[0], ["step", "op_index", "op", "ptr", "cell"]);
+    assert_eq!(lines[4], ["3", "3", "-", "0", "2"]);
+    assert_eq!(lines[5], ["4", "4", ">", "0", "1"]);
+
+    bf(&[
+        "-e",
+        "++[->+<]",
+        "--trace",
+        &path,
+        "--trace-from",
+        "3",
+        "--trace-to",
+        "5",
+    ]);
+    let trace = fs::read_to_string(&path).unwrap();
+    assert_eq!(trace.lines().count(), 1 + 2);
+    assert!(trace.lines().nth(1).unwrap().starts_with("3 "));
+}
+
+#[test]
+fn eval_runs_the_given_program() {
+    let program = "++++++[>++++++++++<-]>+++++.";
+    for eval in ["-e", "--eval"] {
+        let output = bf(&[eval, program, "--binary"]);
+        assert_eq!(output.stdout, b"A");
+    }
+
+    let path = temp_file("eval", "program.b", b"+.");
+    let output = bf(&["-e", program, &path]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("`eval` can't be used together with a program path"));
+}
+
+#[test]
+fn extended_ops_print_to_stderr() {
+    let program = "+>++$!.";
+    let output = bf(&["-e", program, "--extended", "--numeric-output"]);
+    assert_eq!(stderr(&output), "tape: 0:1 1:2\nptr: 1, cell: 2\n");
+    assert!(stdout(&output).starts_with("2 \nperformed 7 operations"));
+
+    let output = bf(&["-e", program, "--numeric-output"]);
+    assert_eq!(stderr(&output), "");
+    assert!(stdout(&output).starts_with("2 \nperformed 5 operations"));
+}
+
+#[test]
+fn infinite_loop_fails_under_strict() {
+    let output = bf(&["-e", "+[]", "--strict"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("found 1 potentially infinite loop(s)"));
+
+    let output = bf(&["-e", "++[-]", "--strict"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn each_labels_every_program() {
+    let first = temp_file("each", "first.b", b"+++++++[>++++++++++<-]>-.");
+    let second = temp_file("each", "second.b", b",.");
+    let output = bf(&[&first, &second, "--input", "x", "--each"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let stdout = stdout(&output);
+    let first_run = stdout.find(&format!("=== {}\nE\n", first)).unwrap();
+    let second_run = stdout.find(&format!("=== {}\nx\n", second)).unwrap();
+    assert!(first_run < second_run);
+    assert_eq!(stdout.matches("completed   1 byte(s)").count(), 2);
+}
+
+#[test]
+fn strict_rejects_unknown_characters() {
+    let output = bf(&["-e", "++(>+.", "--strict", "--numeric-output"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("error at 1:3: unknown character `(`"));
+
+    let output = bf(&["-e", "++(>+.", "--numeric-output"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("1 \n"));
+}
+
+#[test]
+fn compiled_program_translates_to_the_same_c() {
+    let program = "++++++++[>++++++<-]>.";
+    for level in ["1", "2"] {
+        let compiled = temp_file("compiled_to_c", &format!("o{}.bfc", level), b"");
+        let c = temp_file("compiled_to_c", &format!("o{}.c", level), b"");
+        let output = bf(&[
+            "-e",
+            program,
+            "--optimize",
+            level,
+            "--compile-to",
+            &compiled,
+        ]);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        let output = bf(&["--run-compiled", &compiled, "--emit-c", &c]);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+        let c = fs::read_to_string(&c).unwrap();
+        assert!(c.contains("    *p += 8;\n"), "-O{}", level);
+        assert!(c.contains("    p[1] += *p * 6u;\n"), "-O{}", level);
+    }
+}
+
+#[test]
+fn stats_report_the_peak_address_on_the_infinite_tape() {
+    let output = bf(&["-e", "<+>>>", "--infinite-tape", "--stats"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("peak pointer: 2, nonzero cells: 1,"));
+}
+
+#[test]
+fn binary_keeps_bundles_and_the_debugger_off_stdout() {
+    let output = bf(&["-e", "+++.", "--binary", "--all"]);
+    assert_eq!(output.stdout, [3]);
+    assert!(stderr(&output).contains("=== #1"));
+    assert!(stderr(&output).contains("#1       completed   1 byte(s)"));
+
+    let output = bf_with_stdin(&["-e", "+#+.", "--debug", "--binary"], b"p 0\nc\n");
+    assert_eq!(output.stdout, [2]);
+    assert!(stderr(&output).contains("paused at op 0"));
+    assert!(stderr(&output).contains("cell 0 = 0"));
+}
+
+#[test]
+fn json_is_alone_on_stdout() {
+    let output = bf(&["-e", "+++.", "--json", "--dump-memory", "4"]);
+    assert!(stdout(&output).starts_with("{\"total_ops\":4,"));
+    assert_eq!(stdout(&output).lines().count(), 1);
+    assert!(stderr(&output).contains("memory:"));
+
+    let output = bf(&["-e", "+++.", "--json", "--halt-at-op", "."]);
+    assert!(stdout(&output).starts_with('{'));
+    assert_eq!(stdout(&output).lines().count(), 1);
+    assert!(stderr(&output).contains("halted at op 3"));
+
+    for option in ["--stats", "--profile"] {
+        let output = bf(&["-e", "+++.", "--json", option]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains(&format!(
+            "`json` can't be used together with `{}`",
+            &option[2..]
+        )));
+    }
+}
+
+#[test]
+fn optimize_only_accepts_known_levels() {
+    for level in ["3", "5", "x"] {
+        let output = bf(&["--optimize", level, "-e", "+."]);
+        assert_eq!(output.status.code(), Some(1), "{}", level);
+        assert!(stderr(&output).contains("failed to parse `optimize` (0, 1 or 2)"));
+    }
+    let output = bf(&["--optimize", "2", "-e", "+.", "--binary"]);
+    assert_eq!(output.stdout, [1]);
+}